@@ -0,0 +1,316 @@
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use log::{info, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::map::{IntersectionId, LaneId, RoadId, RoadMap};
+use crate::pipeline::{ControlCommand, Snapshot};
+use crate::telemetry::Telemetry;
+
+///longest the server waits for a request before checking it should stop
+const API_POLL: Duration = Duration::from_millis(200);
+///larger request bodies are cut short
+const MAX_BODY: u64 = 64 * 1024;
+const ENDPOINTS: &[&str] = &[
+    "/network",
+    "/lanes",
+    "/costs",
+    "/indicators",
+    "/route",
+    "/coefficients",
+    "/closures",
+    "/overrides",
+    "/metrics",
+];
+
+/// what the HTTP API reads from and writes to
+#[derive(Clone)]
+pub struct ApiState {
+    pub map: Arc<Mutex<RoadMap>>,
+    pub snapshot: Arc<Mutex<Snapshot>>,
+    pub control: Sender<ControlCommand>,
+    pub telemetry: Arc<Telemetry>,
+}
+
+type ApiResult = Result<Value, (u16, String)>;
+
+/// serves the live state of the rerouter as json until `is_running` is cleared
+///
+/// `GET /network`, `/lanes`, `/costs`, `/indicators`, `/coefficients`, `/route?from=a&to=c[&road=a-1]`
+/// `POST /coefficients {"density_coeff": 400}`, `/closures {"lane": "2-3-l", "closed": true}`,
+/// `/overrides {"lane": "1-4-r", "cost": 900}` (a `null` cost removes the override)
+///
+/// `GET /metrics` serves the telemetry in the Prometheus text format rather than json
+pub fn spawn(
+    addr: &str,
+    state: ApiState,
+    is_running: Arc<AtomicBool>,
+) -> io::Result<JoinHandle<()>> {
+    let server = Server::http(addr).map_err(io::Error::other)?;
+    info!("Serving the HTTP API on http://{}", addr);
+    Ok(thread::spawn(move || {
+        while is_running.load(Ordering::Relaxed) {
+            let mut request = match server.recv_timeout(API_POLL) {
+                Ok(Some(request)) => request,
+                Ok(None) => continue,
+                Err(e) => {
+                    warn!("HTTP API failed to receive a request: {}", e);
+                    continue;
+                }
+            };
+            let response = if request.method() == &Method::Get && request.url() == "/metrics" {
+                let metrics = state.telemetry.render(&state.snapshot.lock().unwrap());
+                Response::from_string(metrics).with_header(
+                    "Content-Type: text/plain; version=0.0.4"
+                        .parse::<Header>()
+                        .unwrap(),
+                )
+            } else {
+                let (status, body) = match handle(&mut request, &state) {
+                    Ok(value) => (200, value),
+                    Err((status, error)) => (status, json!({ "error": error })),
+                };
+                Response::from_string(body.to_string())
+                    .with_status_code(status)
+                    .with_header("Content-Type: application/json".parse::<Header>().unwrap())
+            };
+            if let Err(e) = request.respond(response) {
+                warn!("HTTP API failed to respond: {}", e);
+            }
+        }
+    }))
+}
+
+fn handle(request: &mut Request, state: &ApiState) -> ApiResult {
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    let method = request.method().clone();
+    match (&method, path) {
+        (Method::Get, "/network") => Ok(network(&state.map.lock().unwrap())),
+        (Method::Get, "/lanes") => {
+            let snapshot = state.snapshot.lock().unwrap();
+            let mut lanes = snapshot.lanes.clone();
+            lanes.sort_by_key(|l| l.lane);
+            Ok(json!(lanes))
+        }
+        (Method::Get, "/costs") => {
+            let snapshot = state.snapshot.lock().unwrap();
+            let mut costs = snapshot.lane_costs.clone();
+            costs.sort_by_key(|c| c.lane);
+            Ok(json!(costs))
+        }
+        (Method::Get, "/indicators") => {
+            let snapshot = state.snapshot.lock().unwrap();
+            Ok(json!({
+                "routes": snapshot.routes,
+                "leds": snapshot.leds.iter().map(|led| format!("{:?}", led)).collect::<Vec<_>>(),
+            }))
+        }
+        (Method::Get, "/route") => route(&state.map.lock().unwrap(), query),
+        (Method::Get, "/coefficients") => Ok(coefficients(&state.snapshot.lock().unwrap())),
+        (Method::Get, "/overrides") => Ok(json!(state
+            .snapshot
+            .lock()
+            .unwrap()
+            .cost_overrides
+            .iter()
+            .map(|(lane, cost)| json!({ "lane": lane, "cost": cost }))
+            .collect::<Vec<_>>())),
+        (Method::Post, "/coefficients") => {
+            let command = coefficients_command(&read_body(request)?)?;
+            send(state, command)
+        }
+        (Method::Post, "/closures") => {
+            let command = closure_command(&read_body(request)?, &state.map.lock().unwrap())?;
+            send(state, command)
+        }
+        (Method::Post, "/overrides") => {
+            let body: OverrideBody = parse_body(&read_body(request)?)?;
+            let lane = parse::<LaneId>(&body.lane)?;
+            if !state.map.lock().unwrap().roads.contains_key(&lane.0) {
+                return Err((404, format!("no such road '{}'", lane.0)));
+            }
+            if body.cost.is_some_and(|c| !(c >= 0.0 && c.is_finite())) {
+                return Err((
+                    400,
+                    "cost must be a non-negative number or null".to_string(),
+                ));
+            }
+            send(
+                state,
+                ControlCommand::OverrideCost {
+                    lane,
+                    cost: body.cost,
+                },
+            )
+        }
+        _ if ENDPOINTS.contains(&path) => Err((405, format!("{} not allowed on {}", method, path))),
+        _ => Err((404, format!("no such endpoint '{}'", path))),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CoefficientsBody {
+    density_coeff: Option<f64>,
+    vel_coeff: Option<f64>,
+    clearance_coeff: Option<f64>,
+    queue_coeff: Option<f64>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ClosureBody {
+    lane: String,
+    closed: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OverrideBody {
+    lane: String,
+    cost: Option<f64>,
+}
+
+///the command a `/coefficients` body asks for, also taken from other control interfaces
+pub fn coefficients_command(body: &str) -> Result<ControlCommand, (u16, String)> {
+    let body: CoefficientsBody = parse_body(body)?;
+    for coeff in [
+        body.density_coeff,
+        body.vel_coeff,
+        body.clearance_coeff,
+        body.queue_coeff,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        if !(coeff >= 0.0 && coeff.is_finite()) {
+            return Err((400, "coefficients must be non-negative numbers".to_string()));
+        }
+    }
+    Ok(ControlCommand::SetCoefficients {
+        density_coeff: body.density_coeff,
+        vel_coeff: body.vel_coeff,
+        clearance_coeff: body.clearance_coeff,
+        queue_coeff: body.queue_coeff,
+    })
+}
+
+///the command a `/closures` body asks for, also taken from other control interfaces
+pub fn closure_command(body: &str, map: &RoadMap) -> Result<ControlCommand, (u16, String)> {
+    let body: ClosureBody = parse_body(body)?;
+    let lane = parse::<LaneId>(&body.lane)?;
+    if !map.roads.contains_key(&lane.0) {
+        return Err((404, format!("no such road '{}'", lane.0)));
+    }
+    Ok(ControlCommand::SetClosed {
+        lane,
+        closed: body.closed,
+    })
+}
+
+fn read_body(request: &mut Request) -> Result<String, (u16, String)> {
+    let mut body = String::new();
+    request
+        .as_reader()
+        .take(MAX_BODY)
+        .read_to_string(&mut body)
+        .map_err(|e| (400, e.to_string()))?;
+    Ok(body)
+}
+
+fn parse_body<T: for<'de> Deserialize<'de>>(body: &str) -> Result<T, (u16, String)> {
+    serde_json::from_str(body).map_err(|e| (400, format!("invalid body: {}", e)))
+}
+
+fn parse<T: std::str::FromStr<Err = String>>(s: &str) -> Result<T, (u16, String)> {
+    s.parse().map_err(|e| (400, e))
+}
+
+///accepted commands are applied by the pipeline within a cost update
+fn send(state: &ApiState, command: ControlCommand) -> ApiResult {
+    state
+        .control
+        .send(command)
+        .map_err(|_| (503, "the pipeline has stopped".to_string()))?;
+    Ok(json!({ "accepted": true }))
+}
+
+fn network(map: &RoadMap) -> Value {
+    let mut intersections = map.intersections().collect::<Vec<_>>();
+    intersections.sort_by_key(|i| i.id().0);
+    let mut roads = map.roads.values().collect::<Vec<_>>();
+    roads.sort_by_key(|r| r.id());
+    json!({
+        "intersections": intersections.iter().map(|i| json!({
+            "id": i.id(),
+            "pos": i.pos(),
+            "roads": i.roads(),
+        })).collect::<Vec<_>>(),
+        "roads": roads.iter().map(|road| {
+            let lanes = [0, 1].map(|n| {
+                let lane = LaneId(road.id(), n);
+                json!({
+                    "id": lane,
+                    "from": lane.entry_id(),
+                    "to": lane.exit_id(),
+                    "static_cost": road.cost_from(&lane.entry_id(), false),
+                    "cost": road.cost_from(&lane.entry_id(), true),
+                    "closed": map.is_closed(&lane),
+                })
+            });
+            json!({ "id": road.id(), "length": road.length(), "lanes": lanes })
+        }).collect::<Vec<_>>(),
+    })
+}
+
+///best and shortest first road from `from` to `to`, optionally arriving at `from` on `road`
+fn route(map: &RoadMap, query: &str) -> ApiResult {
+    let param = |name: &str| {
+        query
+            .split('&')
+            .filter_map(|p| p.split_once('='))
+            .find(|(k, _)| *k == name)
+            .map(|(_, v)| v)
+    };
+    let endpoint = |name: &str| -> Result<IntersectionId, (u16, String)> {
+        let id = parse::<IntersectionId>(param(name).ok_or((400, format!("missing '{}'", name)))?)?;
+        if map.intersections().any(|i| i.id() == id) {
+            Ok(id)
+        } else {
+            Err((404, format!("no such intersection '{}'", id)))
+        }
+    };
+    let (from, to) = (endpoint("from")?, endpoint("to")?);
+    let road = param("road").map(parse::<RoadId>).transpose()?;
+
+    let (cost, best) = map.best_direction(from, to, road.as_ref());
+    let (length, shortest) = map.shortest_direction(from, to, road.as_ref());
+    // unreachable destinations have an infinite cost, which serializes as null
+    Ok(json!({
+        "from": from,
+        "to": to,
+        "road": best,
+        "cost": cost,
+        "shortest": { "road": shortest, "cost": length },
+    }))
+}
+
+///the cost model and its coefficients, as served on `GET /coefficients`
+pub fn coefficients(snapshot: &Snapshot) -> Value {
+    let params = &snapshot.cost_params;
+    json!({
+        "cost_model": snapshot.cost_model,
+        "density_coeff": params.density_coeff,
+        "vel_coeff": params.vel_coeff,
+        "clearance_coeff": params.clearance_coeff,
+        "queue_coeff": params.queue_coeff,
+    })
+}
@@ -0,0 +1,310 @@
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+///a camera pixel and the map point it corresponds to
+pub type PointPair = ((f64, f64), (f64, f64));
+
+/// projective transform from camera pixels to map coordinates (meters)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Homography(pub [[f64; 3]; 3]);
+
+impl Homography {
+    pub fn identity() -> Self {
+        Self([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]])
+    }
+
+    ///estimates the homography from at least 4 (pixel, world) point pairs
+    ///using a least squares fit with `h[2][2]` fixed to 1
+    pub fn from_correspondences(points: &[PointPair]) -> Option<Self> {
+        if points.len() < 4 {
+            return None;
+        }
+        // hundreds of pixels against tens of meters make the normal equations badly conditioned,
+        // so both point sets are normalized first (Hartley) and the fit is mapped back after
+        let pixels = normalization(&points.iter().map(|(p, _)| *p).collect::<Vec<_>>())?;
+        let world = normalization(&points.iter().map(|(_, w)| *w).collect::<Vec<_>>())?;
+
+        // normal equations (AᵀA)h = Aᵀb of the 2n x 8 system
+        let mut ata = [[0.0; 8]; 8];
+        let mut atb = [0.0; 8];
+        for (p, w) in points {
+            let ((x, y), (u, v)) = (pixels.apply(*p), world.apply(*w));
+            let rows = [
+                ([x, y, 1.0, 0.0, 0.0, 0.0, -u * x, -u * y], u),
+                ([0.0, 0.0, 0.0, x, y, 1.0, -v * x, -v * y], v),
+            ];
+            for (row, rhs) in &rows {
+                for i in 0..8 {
+                    atb[i] += row[i] * rhs;
+                    for j in 0..8 {
+                        ata[i][j] += row[i] * row[j];
+                    }
+                }
+            }
+        }
+
+        let h = solve_linear(ata, atb)?;
+        let normalized = [[h[0], h[1], h[2]], [h[3], h[4], h[5]], [h[6], h[7], 1.0]];
+        let m = multiply(&world.inverse()?.0, &multiply(&normalized, &pixels.0));
+        if m[2][2].abs() < 1e-12 {
+            return None;
+        }
+        Some(Self(m.map(|row| row.map(|v| v / m[2][2]))))
+    }
+
+    pub fn apply(&self, p: (f64, f64)) -> (f64, f64) {
+        let h = &self.0;
+        let w = h[2][0] * p.0 + h[2][1] * p.1 + h[2][2];
+        (
+            (h[0][0] * p.0 + h[0][1] * p.1 + h[0][2]) / w,
+            (h[1][0] * p.0 + h[1][1] * p.1 + h[1][2]) / w,
+        )
+    }
+
+    ///transform from map coordinates back to camera pixels
+    pub fn inverse(&self) -> Option<Self> {
+        let m = &self.0;
+        let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| {
+            m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+        };
+        #[rustfmt::skip]
+        let adj = [
+            [cofactor(1, 2, 1, 2), -cofactor(0, 2, 1, 2), cofactor(0, 1, 1, 2)],
+            [-cofactor(1, 2, 0, 2), cofactor(0, 2, 0, 2), -cofactor(0, 1, 0, 2)],
+            [cofactor(1, 2, 0, 1), -cofactor(0, 2, 0, 1), cofactor(0, 1, 0, 1)],
+        ];
+        let det = m[0][0] * adj[0][0] + m[0][1] * adj[1][0] + m[0][2] * adj[2][0];
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        Some(Self(adj.map(|row| row.map(|v| v / det))))
+    }
+
+    ///meters per pixel around `p`, used to convert pixel speeds reported by the detector
+    pub fn scale_at(&self, p: (f64, f64)) -> f64 {
+        let h = &self.0;
+        let w = h[2][0] * p.0 + h[2][1] * p.1 + h[2][2];
+        let (u, v) = self.apply(p);
+        let du_dx = (h[0][0] - u * h[2][0]) / w;
+        let du_dy = (h[0][1] - u * h[2][1]) / w;
+        let dv_dx = (h[1][0] - v * h[2][0]) / w;
+        let dv_dy = (h[1][1] - v * h[2][1]) / w;
+        (du_dx * dv_dy - du_dy * dv_dx).abs().sqrt()
+    }
+}
+
+///similarity transform moving the centroid of `points` to the origin and their average distance
+///from it to √2, `None` if the points all coincide
+fn normalization(points: &[(f64, f64)]) -> Option<Homography> {
+    let n = points.len() as f64;
+    let (cx, cy) = points
+        .iter()
+        .fold((0.0, 0.0), |(sx, sy), (x, y)| (sx + x / n, sy + y / n));
+    let mean_dist = points
+        .iter()
+        .map(|(x, y)| (x - cx).hypot(y - cy))
+        .sum::<f64>()
+        / n;
+    if mean_dist < 1e-12 {
+        return None;
+    }
+    let s = std::f64::consts::SQRT_2 / mean_dist;
+    Some(Homography([
+        [s, 0.0, -s * cx],
+        [0.0, s, -s * cy],
+        [0.0, 0.0, 1.0],
+    ]))
+}
+
+fn multiply(a: &[[f64; 3]; 3], b: &[[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    let mut m = [[0.0; 3]; 3];
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    m
+}
+
+///gaussian elimination with partial pivoting, `None` if the system is singular
+fn solve_linear(mut a: [[f64; 8]; 8], mut b: [f64; 8]) -> Option<[f64; 8]> {
+    for col in 0..8 {
+        let pivot = (col..8).max_by(|i, j| a[*i][col].abs().total_cmp(&a[*j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+
+        let pivot_row = a[col];
+        for row in col + 1..8 {
+            let factor = a[row][col] / pivot_row[col];
+            for (k, value) in a[row].iter_mut().enumerate().skip(col) {
+                *value -= factor * pivot_row[k];
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+
+    let mut x = [0.0; 8];
+    for row in (0..8).rev() {
+        let sum = (row + 1..8).map(|k| a[row][k] * x[k]).sum::<f64>();
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+    Some(x)
+}
+
+/// per camera calibration, stored as a plain text file:
+/// ```text
+/// camera 1
+/// point <px> <py> <wx> <wy>
+/// h <h00> <h01> <h02>
+/// h <h10> <h11> <h12>
+/// h <h20> <h21> <h22>
+/// ```
+/// the `h` rows are optional, when missing the homography is fitted from the `point` lines
+#[derive(Debug, Clone)]
+pub struct Calibration {
+    pub camera: String,
+    pub points: Vec<PointPair>,
+    pub homography: Homography,
+}
+
+impl Calibration {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, io::Error> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    fn parse(contents: &str) -> Result<Self, io::Error> {
+        let invalid = |msg: String| io::Error::new(ErrorKind::InvalidData, msg);
+
+        let mut camera = String::from("0");
+        let mut points = vec![];
+        let mut rows = vec![];
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let key = words.next().unwrap();
+            if key == "camera" {
+                camera = words.collect::<Vec<&str>>().join(" ");
+                continue;
+            }
+
+            let values = words
+                .map(|w| w.parse::<f64>())
+                .collect::<Result<Vec<f64>, _>>()
+                .map_err(|e| invalid(format!("line {}: {}", n + 1, e)))?;
+            match (key, values.len()) {
+                ("point", 4) => points.push(((values[0], values[1]), (values[2], values[3]))),
+                ("h", 3) => rows.push([values[0], values[1], values[2]]),
+                _ => return Err(invalid(format!("line {}: unexpected '{}'", n + 1, line))),
+            }
+        }
+
+        let homography = match rows.len() {
+            3 => Homography([rows[0], rows[1], rows[2]]),
+            0 => Homography::from_correspondences(&points).ok_or_else(|| {
+                invalid("need at least 4 non-degenerate points to fit a homography".to_string())
+            })?,
+            n => return Err(invalid(format!("expected 3 'h' rows, found {}", n))),
+        };
+        // the tracker maps positions back to pixels for display
+        if homography.inverse().is_none() {
+            return Err(invalid("the homography is not invertible".to_string()));
+        }
+
+        Ok(Self {
+            camera,
+            points,
+            homography,
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), io::Error> {
+        let mut s = String::from("# camera pixels -> map coordinates in meters\n");
+        s += &format!("camera {}\n", self.camera);
+        for ((px, py), (wx, wy)) in &self.points {
+            s += &format!("point {} {} {} {}\n", px, py, wx, wy);
+        }
+        for row in &self.homography.0 {
+            s += &format!("h {} {} {}\n", row[0], row[1], row[2]);
+        }
+        fs::write(path, s)
+    }
+
+    ///largest distance (in meters) between a calibration point and its projection
+    pub fn max_reprojection_error(&self) -> f64 {
+        self.points
+            .iter()
+            .map(|(p, w)| {
+                let (u, v) = self.homography.apply(*p);
+                ((u - w.0).powi(2) + (v - w.1).powi(2)).sqrt()
+            })
+            .fold(0.0, f64::max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///a camera looking down at an angle, pixels in the thousands and meters in the tens
+    const CAMERA: Homography = Homography([
+        [0.031, 0.004, -12.0],
+        [-0.002, 0.045, -3.5],
+        [0.00001, 0.0004, 1.0],
+    ]);
+
+    fn distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+        (a.0 - b.0).hypot(a.1 - b.1)
+    }
+
+    #[test]
+    fn fit_recovers_the_homography() {
+        let points = [
+            (120.0, 80.0),
+            (1800.0, 95.0),
+            (1750.0, 1020.0),
+            (160.0, 990.0),
+        ]
+        .iter()
+        .chain(&[(960.0, 540.0), (400.0, 700.0), (1500.0, 300.0)])
+        .map(|p| (*p, CAMERA.apply(*p)))
+        .collect::<Vec<PointPair>>();
+        let fitted = Homography::from_correspondences(&points).unwrap();
+        for (pixel, world) in &points {
+            assert!(distance(fitted.apply(*pixel), *world) < 1e-6);
+        }
+        assert!(distance(fitted.apply((1000.0, 800.0)), CAMERA.apply((1000.0, 800.0))) < 1e-6);
+    }
+
+    #[test]
+    fn fit_needs_four_distinct_points() {
+        let points = [((0.0, 0.0), (0.0, 0.0)); 4];
+        assert!(Homography::from_correspondences(&points[..3]).is_none());
+        assert!(Homography::from_correspondences(&points).is_none());
+    }
+
+    #[test]
+    fn inverse_round_trips() {
+        let inverse = CAMERA.inverse().unwrap();
+        for pixel in [(0.0, 0.0), (960.0, 540.0), (1919.0, 1079.0)] {
+            assert!(distance(inverse.apply(CAMERA.apply(pixel)), pixel) < 1e-6);
+        }
+        assert!(
+            Homography([[1.0, 2.0, 3.0], [2.0, 4.0, 6.0], [0.0, 0.0, 1.0]])
+                .inverse()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn singular_homography_is_rejected() {
+        let e = Calibration::parse("h 1 2 3\nh 2 4 6\nh 0 0 1\n").unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+}
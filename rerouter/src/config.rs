@@ -0,0 +1,591 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use traffic_cost::CostParams;
+
+use crate::cost_smoothing::CostSmoother;
+use crate::light_controller::Led;
+use crate::logger::LogFilter;
+use crate::mqtt;
+
+///config file read when no `--config` is given, skipped if it doesn't exist
+const DEFAULT_CONFIG_FILE: &str = "rerouter.conf";
+///boolean settings, given on the command line without a value
+const FLAGS: [&str; 3] = ["headless", "console", "forecast_routing"];
+///a directory holding one of these is taken as the default location of the files the rerouter reads
+const ROOT_MARKERS: [&str; 2] = [DEFAULT_CONFIG_FILE, "road-masks"];
+///checkout the binary was built from, only used as the default location by debug builds
+const BUILD_ROOT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/..");
+
+///every setting and what it is for, in the order `--help` lists them
+const SETTINGS: &[(&str, &str)] = &[
+    (
+        "detector",
+        "vehicle detector script, also taken as the first plain argument",
+    ),
+    ("python", "interpreter the detector script is run with"),
+    ("serial_port", "serial port of the light controller"),
+    ("baud_rate", "baud rate of the light controller"),
+    (
+        "safe_leds",
+        "leds left lit on shutdown, space separated, ie `B_L_a_1 C_U_b_2`",
+    ),
+    (
+        "shutdown_timeout",
+        "seconds the threads are given to stop on shutdown",
+    ),
+    (
+        "log_level",
+        "console log level, optionally by module, ie `info light_controller=debug`",
+    ),
+    (
+        "log_file",
+        "file json log lines are appended to, empty to log to the console only",
+    ),
+    ("log_file_level", "log level of the log file, as log_level"),
+    ("headless", "run without the dashboard window"),
+    (
+        "console",
+        "take operator commands on stdin, `help` lists them",
+    ),
+    (
+        "api_addr",
+        "address the HTTP API and its Prometheus metrics listen on, empty to turn it off",
+    ),
+    (
+        "stream_addr",
+        "address the WebSocket stream listens on, empty to turn it off",
+    ),
+    ("stream_interval", "seconds between two stream updates"),
+    (
+        "mqtt_broker",
+        "MQTT broker as `host[:port]`, empty to turn MQTT off",
+    ),
+    (
+        "mqtt_client_id",
+        "client id the rerouter connects to the broker with",
+    ),
+    (
+        "mqtt_interval",
+        "seconds between two publications of the lane metrics",
+    ),
+    (
+        "mqtt_routes_topic",
+        "topic the route decisions are published to when they change",
+    ),
+    (
+        "mqtt_lanes_topic",
+        "topic the lane metrics are published to",
+    ),
+    (
+        "mqtt_incidents_topic",
+        "topic incidents are published to when raised and cleared",
+    ),
+    (
+        "mqtt_closures_topic",
+        "topic lane closures are taken from, ie `{\"lane\": \"2-3-l\", \"closed\": true}`",
+    ),
+    (
+        "mqtt_coefficients_topic",
+        "topic cost coefficients are taken from, ie `{\"density_coeff\": 400}`",
+    ),
+    ("font", "dashboard font"),
+    ("map_outline", "dashboard background image"),
+    (
+        "road_masks",
+        "directory of lane masks, used without a lane geometry file",
+    ),
+    ("lane_geometry", "lane polygons and centrelines"),
+    (
+        "calibration",
+        "camera calibration, also written by `rerouter calibrate`",
+    ),
+    (
+        "pce_weights",
+        "passenger car equivalent of each vehicle class",
+    ),
+    (
+        "metrics_dir",
+        "directory lane metrics and route decisions are stored in",
+    ),
+    (
+        "cost_model",
+        "lane cost model, one of traffic_cost::MODEL_NAMES",
+    ),
+    (
+        "cost_smoothing",
+        "lane cost smoothing, ie `ema:5 3-4-r=median:30`",
+    ),
+    ("density_coeff", "weight of the lane density in its cost"),
+    ("vel_coeff", "weight of the lane speed in its cost"),
+    (
+        "clearance_coeff",
+        "weight of the gap to the junction in the lane cost",
+    ),
+    ("queue_coeff", "weight of the queue length in the lane cost"),
+    (
+        "forecast_routing",
+        "route on the cost forecast for when each road is reached",
+    ),
+    (
+        "track_timeout",
+        "seconds without a detection before a vehicle is dropped",
+    ),
+];
+
+/// settings of the rerouter
+///
+/// read from a config file of `key = value` lines (`#` starts a comment at the start of a line or
+/// after whitespace, so MQTT wildcards are kept), then overridden by `--key value` or
+/// `--key=value` arguments, with `-` and `_` interchangeable in keys. boolean settings are given
+/// on the command line as plain flags, ie `--headless`
+///
+/// relative paths are taken from the directory of the config file they are in, the working
+/// directory on the command line and `root()` for the defaults
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub detector: Option<PathBuf>,
+    pub python: String,
+    pub serial_port: String,
+    pub baud_rate: u32,
+    pub safe_leds: Vec<Led>,
+    pub shutdown_timeout: Duration,
+    pub log_level: String,
+    pub log_file: Option<PathBuf>,
+    pub log_file_level: String,
+    pub headless: bool,
+    pub console: bool,
+    pub api_addr: String,
+    pub stream_addr: String,
+    pub stream_interval: Duration,
+    pub mqtt_broker: String,
+    pub mqtt_client_id: String,
+    pub mqtt_interval: Duration,
+    pub mqtt_routes_topic: String,
+    pub mqtt_lanes_topic: String,
+    pub mqtt_incidents_topic: String,
+    pub mqtt_closures_topic: String,
+    pub mqtt_coefficients_topic: String,
+    pub font: PathBuf,
+    pub map_outline: PathBuf,
+    pub road_masks: PathBuf,
+    pub lane_geometry: PathBuf,
+    pub calibration: PathBuf,
+    pub pce_weights: PathBuf,
+    pub metrics_dir: PathBuf,
+    pub cost_model: String,
+    pub cost_smoothing: String,
+    pub cost_params: CostParams,
+    pub forecast_routing: bool,
+    pub track_timeout: Duration,
+}
+
+///`line` up to a `#` at its start or after whitespace
+fn strip_comment(line: &str) -> &str {
+    let mut after_blank = true;
+    for (i, c) in line.char_indices() {
+        if c == '#' && after_blank {
+            return &line[..i];
+        }
+        after_blank = c.is_whitespace();
+    }
+    line
+}
+
+///default location of the files the rerouter reads: the closest directory to the executable (its
+///own or one above it) with a `ROOT_MARKERS` file, otherwise the checkout in debug builds and the
+///directory of the executable in release builds
+fn root() -> PathBuf {
+    let exe_dir = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf));
+    let found = exe_dir.as_deref().and_then(|dir| {
+        dir.ancestors()
+            .find(|dir| ROOT_MARKERS.iter().any(|m| dir.join(m).exists()))
+            .map(Path::to_path_buf)
+    });
+    match found {
+        Some(dir) => dir,
+        None if cfg!(debug_assertions) => PathBuf::from(BUILD_ROOT),
+        None => exe_dir.unwrap_or_else(|| PathBuf::from(".")),
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let root = root();
+        Self {
+            detector: None,
+            python: "python3".to_string(),
+            serial_port: "/dev/ttyACM0".to_string(),
+            baud_rate: 115200,
+            safe_leds: vec![],
+            shutdown_timeout: Duration::from_secs(2),
+            log_level: "info".to_string(),
+            log_file: None,
+            log_file_level: "debug".to_string(),
+            headless: false,
+            console: false,
+            api_addr: "127.0.0.1:8080".to_string(),
+            stream_addr: "127.0.0.1:8081".to_string(),
+            stream_interval: Duration::from_millis(200),
+            mqtt_broker: String::new(),
+            mqtt_client_id: "rerouter".to_string(),
+            mqtt_interval: Duration::from_secs(1),
+            mqtt_routes_topic: "rerouter/routes".to_string(),
+            mqtt_lanes_topic: "rerouter/lanes".to_string(),
+            mqtt_incidents_topic: "rerouter/incidents".to_string(),
+            mqtt_closures_topic: "rerouter/control/closures".to_string(),
+            mqtt_coefficients_topic: "rerouter/control/coefficients".to_string(),
+            font: root.join("fonts/FiraCode-Medium.ttf"),
+            map_outline: root.join("map-outline.png"),
+            road_masks: root.join("road-masks"),
+            lane_geometry: root.join("lane-geometry.txt"),
+            calibration: root.join("calibration.txt"),
+            pce_weights: root.join("pce-weights.txt"),
+            metrics_dir: root.join("metrics"),
+            cost_model: "density".to_string(),
+            cost_smoothing: "ema:5".to_string(),
+            cost_params: CostParams::default(),
+            forecast_routing: false,
+            track_timeout: Duration::from_millis(1300),
+        }
+    }
+}
+
+impl Config {
+    ///the config file (`--config <path>` or the default one) overridden by the other arguments,
+    ///`args` leaves out the program name
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut config = Self::default();
+
+        let config_path = match args.iter().position(|a| a == "--config") {
+            Some(i) => Some(PathBuf::from(
+                args.get(i + 1).ok_or("--config needs a file")?,
+            )),
+            None => args
+                .iter()
+                .find_map(|a| a.strip_prefix("--config="))
+                .map(PathBuf::from),
+        };
+        match config_path {
+            Some(path) => config.load_file(&path)?,
+            None => {
+                let path = root().join(DEFAULT_CONFIG_FILE);
+                if path.exists() {
+                    config.load_file(&path)?;
+                }
+            }
+        }
+
+        let cwd = Path::new(".");
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let option = match arg.strip_prefix("--") {
+                Some(option) => option,
+                None => {
+                    config.set("detector", arg, cwd)?;
+                    continue;
+                }
+            };
+            let (key, value) = match option.split_once('=') {
+                Some((key, value)) => (key.replace('-', "_"), value.to_string()),
+                None => {
+                    let key = option.replace('-', "_");
+                    if FLAGS.contains(&key.as_str()) {
+                        (key, "true".to_string())
+                    } else {
+                        let value = args.next().ok_or(format!("--{} needs a value", option))?;
+                        (key, value.clone())
+                    }
+                }
+            };
+            if key != "config" {
+                config.set(&key, &value, cwd)?;
+            }
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    ///splits the arguments of a subcommand into its plain arguments and the `--key value` settings
+    ///for `from_args`
+    pub fn split_settings(args: &[String]) -> (Vec<String>, Vec<String>) {
+        let (mut plain, mut settings) = (vec![], vec![]);
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some(option) => {
+                    settings.push(arg.clone());
+                    let key = option.replace('-', "_");
+                    if !option.contains('=') && !FLAGS.contains(&key.as_str()) {
+                        settings.extend(args.next().cloned());
+                    }
+                }
+                None => plain.push(arg.clone()),
+            }
+        }
+        (plain, settings)
+    }
+
+    fn load_file(&mut self, path: &Path) -> Result<(), String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config {}: {}", path.display(), e))?;
+        let base = path.parent().unwrap_or(Path::new("."));
+        for (n, line) in contents.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line.split_once('=').ok_or(format!(
+                "{} line {}: expected 'key = value'",
+                path.display(),
+                n + 1
+            ))?;
+            self.set(&key.trim().replace('-', "_"), value.trim(), base)
+                .map_err(|e| format!("{} line {}: {}", path.display(), n + 1, e))?;
+        }
+        Ok(())
+    }
+
+    ///sets one setting from its text form, relative paths are taken from `base`
+    fn set(&mut self, key: &str, value: &str, base: &Path) -> Result<(), String> {
+        let path = || base.join(value);
+        let number = || {
+            value
+                .parse::<f64>()
+                .map_err(|e| format!("{} '{}': {}", key, value, e))
+        };
+        let seconds = || {
+            let secs = number()?;
+            if !(secs > 0.0 && secs.is_finite()) {
+                return Err(format!("{} '{}': must be a positive time", key, value));
+            }
+            Ok(Duration::from_secs_f64(secs))
+        };
+        let flag = || match value {
+            "true" | "on" | "yes" => Ok(true),
+            "false" | "off" | "no" => Ok(false),
+            _ => Err(format!("{} '{}': expected true or false", key, value)),
+        };
+        match key {
+            "detector" => self.detector = Some(path()),
+            "python" => self.python = value.to_string(),
+            "serial_port" => self.serial_port = value.to_string(),
+            "baud_rate" => {
+                self.baud_rate = value
+                    .parse()
+                    .map_err(|e| format!("{} '{}': {}", key, value, e))?
+            }
+            "safe_leds" => {
+                self.safe_leds = value
+                    .split_whitespace()
+                    .map(|led| led.parse())
+                    .collect::<Result<_, _>>()?
+            }
+            "shutdown_timeout" => self.shutdown_timeout = seconds()?,
+            "log_level" => self.log_level = value.to_string(),
+            "log_file" => self.log_file = Some(path()).filter(|_| !value.is_empty()),
+            "log_file_level" => self.log_file_level = value.to_string(),
+            "headless" => self.headless = flag()?,
+            "console" => self.console = flag()?,
+            "api_addr" => self.api_addr = value.to_string(),
+            "stream_addr" => self.stream_addr = value.to_string(),
+            "stream_interval" => self.stream_interval = seconds()?,
+            "mqtt_broker" => self.mqtt_broker = value.to_string(),
+            "mqtt_client_id" => self.mqtt_client_id = value.to_string(),
+            "mqtt_interval" => self.mqtt_interval = seconds()?,
+            "mqtt_routes_topic" => self.mqtt_routes_topic = value.to_string(),
+            "mqtt_lanes_topic" => self.mqtt_lanes_topic = value.to_string(),
+            "mqtt_incidents_topic" => self.mqtt_incidents_topic = value.to_string(),
+            "mqtt_closures_topic" => self.mqtt_closures_topic = value.to_string(),
+            "mqtt_coefficients_topic" => self.mqtt_coefficients_topic = value.to_string(),
+            "font" => self.font = path(),
+            "map_outline" => self.map_outline = path(),
+            "road_masks" => self.road_masks = path(),
+            "lane_geometry" => self.lane_geometry = path(),
+            "calibration" => self.calibration = path(),
+            "pce_weights" => self.pce_weights = path(),
+            "metrics_dir" => self.metrics_dir = path(),
+            "cost_model" => self.cost_model = value.to_string(),
+            "cost_smoothing" => self.cost_smoothing = value.to_string(),
+            "density_coeff" => self.cost_params.density_coeff = number()?,
+            "vel_coeff" => self.cost_params.vel_coeff = number()?,
+            "clearance_coeff" => self.cost_params.clearance_coeff = number()?,
+            "queue_coeff" => self.cost_params.queue_coeff = number()?,
+            "forecast_routing" => self.forecast_routing = flag()?,
+            "track_timeout" => self.track_timeout = seconds()?,
+            _ => return Err(format!("unknown setting '{}'", key)),
+        }
+        Ok(())
+    }
+
+    ///the text form of a setting, as `set` takes it
+    fn get(&self, key: &str) -> String {
+        let path = |p: &Path| p.display().to_string();
+        match key {
+            "detector" => self.detector.as_deref().map(path).unwrap_or_default(),
+            "python" => self.python.clone(),
+            "serial_port" => self.serial_port.clone(),
+            "baud_rate" => self.baud_rate.to_string(),
+            "safe_leds" => self
+                .safe_leds
+                .iter()
+                .map(|led| format!("{:?}", led))
+                .collect::<Vec<_>>()
+                .join(" "),
+            "shutdown_timeout" => self.shutdown_timeout.as_secs_f64().to_string(),
+            "log_level" => self.log_level.clone(),
+            "log_file" => self.log_file.as_deref().map(path).unwrap_or_default(),
+            "log_file_level" => self.log_file_level.clone(),
+            "headless" => self.headless.to_string(),
+            "console" => self.console.to_string(),
+            "api_addr" => self.api_addr.clone(),
+            "stream_addr" => self.stream_addr.clone(),
+            "stream_interval" => self.stream_interval.as_secs_f64().to_string(),
+            "mqtt_broker" => self.mqtt_broker.clone(),
+            "mqtt_client_id" => self.mqtt_client_id.clone(),
+            "mqtt_interval" => self.mqtt_interval.as_secs_f64().to_string(),
+            "mqtt_routes_topic" => self.mqtt_routes_topic.clone(),
+            "mqtt_lanes_topic" => self.mqtt_lanes_topic.clone(),
+            "mqtt_incidents_topic" => self.mqtt_incidents_topic.clone(),
+            "mqtt_closures_topic" => self.mqtt_closures_topic.clone(),
+            "mqtt_coefficients_topic" => self.mqtt_coefficients_topic.clone(),
+            "font" => path(&self.font),
+            "map_outline" => path(&self.map_outline),
+            "road_masks" => path(&self.road_masks),
+            "lane_geometry" => path(&self.lane_geometry),
+            "calibration" => path(&self.calibration),
+            "pce_weights" => path(&self.pce_weights),
+            "metrics_dir" => path(&self.metrics_dir),
+            "cost_model" => self.cost_model.clone(),
+            "cost_smoothing" => self.cost_smoothing.clone(),
+            "density_coeff" => self.cost_params.density_coeff.to_string(),
+            "vel_coeff" => self.cost_params.vel_coeff.to_string(),
+            "clearance_coeff" => self.cost_params.clearance_coeff.to_string(),
+            "queue_coeff" => self.cost_params.queue_coeff.to_string(),
+            "forecast_routing" => self.forecast_routing.to_string(),
+            "track_timeout" => self.track_timeout.as_secs_f64().to_string(),
+            _ => String::new(),
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.baud_rate == 0 {
+            return Err("baud_rate must be positive".to_string());
+        }
+        for (key, filter) in [
+            ("log_level", &self.log_level),
+            ("log_file_level", &self.log_file_level),
+        ] {
+            filter
+                .parse::<LogFilter>()
+                .map_err(|e| format!("invalid {}: {}", key, e))?;
+        }
+        let params = &self.cost_params;
+        for (key, coeff) in [
+            ("density_coeff", params.density_coeff),
+            ("vel_coeff", params.vel_coeff),
+            ("clearance_coeff", params.clearance_coeff),
+            ("queue_coeff", params.queue_coeff),
+        ] {
+            if !(coeff >= 0.0 && coeff.is_finite()) {
+                return Err(format!("{} must be a non-negative number", key));
+            }
+        }
+        if traffic_cost::from_name(&self.cost_model).is_none() {
+            return Err(format!(
+                "unknown cost_model '{}', expected one of {:?}",
+                self.cost_model,
+                traffic_cost::MODEL_NAMES
+            ));
+        }
+        CostSmoother::from_spec(&self.cost_smoothing)
+            .map_err(|e| format!("invalid cost_smoothing: {}", e))?;
+        if !self.mqtt_broker.is_empty() {
+            mqtt::parse_broker(&self.mqtt_broker)
+                .map_err(|e| format!("invalid mqtt_broker: {}", e))?;
+        }
+        Ok(())
+    }
+
+    ///checks the files a live run needs are there, returning the detector script
+    pub fn check_run_files(&self) -> Result<PathBuf, String> {
+        let detector = self
+            .detector
+            .clone()
+            .ok_or("no vehicle detector script given")?;
+        let mut required = vec![("detector", &detector)];
+        if !self.lane_geometry.exists() {
+            required.push(("road_masks", &self.road_masks));
+        }
+        if !self.headless {
+            required.push(("font", &self.font));
+            required.push(("map_outline", &self.map_outline));
+        }
+        for (key, path) in required {
+            if !path.exists() {
+                return Err(format!("{} {} doesn't exist", key, path.display()));
+            }
+        }
+        Ok(detector)
+    }
+
+    ///usage and every setting with its current value
+    pub fn help(&self) -> String {
+        let mut s = String::from(
+            "usage: rerouter [--config <file>] [--<setting> <value>]... [detector script]\n       \
+             rerouter calibrate <points file> [output file] [camera]\n       \
+             rerouter query <lanes|routes> <from> <to> [lane|road]\n\n\
+             settings, with their values after the config file:\n",
+        );
+        for (key, doc) in SETTINGS {
+            s += &format!("  {:<23} {}\n  {:<23} = {}\n", key, doc, "", self.get(key));
+        }
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn comments_start_at_a_separate_hash() {
+        assert_eq!(strip_comment("# a comment"), "");
+        assert_eq!(
+            strip_comment("baud_rate = 9600 # slow"),
+            "baud_rate = 9600 "
+        );
+        assert_eq!(
+            strip_comment("baud_rate = 9600\t# slow"),
+            "baud_rate = 9600\t"
+        );
+        assert_eq!(
+            strip_comment("mqtt_closures_topic = rerouter/control/#"),
+            "mqtt_closures_topic = rerouter/control/#"
+        );
+        assert_eq!(strip_comment("python = py#3"), "python = py#3");
+    }
+
+    #[test]
+    fn subcommand_settings_are_split_off() {
+        let args = [
+            "lanes",
+            "--config",
+            "a.conf",
+            "2024-01-01",
+            "--headless",
+            "--metrics-dir=m",
+        ]
+        .map(String::from);
+        let (plain, settings) = Config::split_settings(&args);
+        assert_eq!(plain, ["lanes", "2024-01-01"]);
+        assert_eq!(
+            settings,
+            ["--config", "a.conf", "--headless", "--metrics-dir=m"]
+        );
+    }
+}
@@ -0,0 +1,281 @@
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use serde_json::json;
+
+use crate::api;
+use crate::light_controller::{Led, LedCommand};
+use crate::map::{IntersectionId, LaneId, RoadId, RoadMap};
+use crate::pipeline::{ControlCommand, Snapshot};
+
+const HELP: &str = "\
+route <from> <to> [road]   best and shortest first road, optionally arriving on road, ie route a c
+cost <road>                static and dynamic cost of both directions, ie cost 1-4
+close <road> [fwd|bck]     closes both directions of a road or one, ie close 2-3 fwd
+open <road> [fwd|bck]      reopens them
+set <coeff> <value>        sets the density, vel, clearance or queue coefficient, ie set density 400
+led <led> <on|off>         until the route decisions next change, ie led B_L_a_1 on
+vehicles <road|lane>       vehicles tracked on a road or lane, ie vehicles 3-4
+dump                       coefficients, lanes, route decisions, leds, incidents and cost overrides
+help";
+
+/// what the console reads and changes
+pub struct ConsoleState {
+    pub map: Arc<Mutex<RoadMap>>,
+    pub snapshot: Arc<Mutex<Snapshot>>,
+    pub control: Sender<ControlCommand>,
+    pub leds: Sender<LedCommand>,
+}
+
+/// runs operator commands read from stdin until it is closed or `is_running` is cleared
+///
+/// the thread is left blocked on stdin when the rerouter stops, reads from it can't be interrupted
+pub fn spawn(state: ConsoleState, is_running: Arc<AtomicBool>) {
+    thread::spawn(move || {
+        prompt();
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else { return };
+            if !is_running.load(Ordering::Relaxed) {
+                return;
+            }
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            if !words.is_empty() {
+                match run(&words, &state) {
+                    Ok(out) => println!("{}", out),
+                    Err(e) => println!("error: {}", e),
+                }
+            }
+            prompt();
+        }
+    });
+}
+
+fn prompt() {
+    print!("> ");
+    let _ = io::stdout().flush();
+}
+
+fn run(words: &[&str], state: &ConsoleState) -> Result<String, String> {
+    match words {
+        ["help"] => Ok(HELP.to_string()),
+        ["route", from, to] => route(state, from, to, None),
+        ["route", from, to, road] => route(state, from, to, Some(road)),
+        ["cost", road] => cost(state, road),
+        [command @ ("close" | "open"), road] => close(state, *command == "close", road, None),
+        [command @ ("close" | "open"), road, direction] => {
+            close(state, *command == "close", road, Some(direction))
+        }
+        ["set", coeff, value] => set(state, coeff, value),
+        ["led", led, on] => set_led(state, led, on),
+        ["vehicles", road] => vehicles(state, road),
+        ["dump"] => Ok(dump(&state.snapshot.lock().unwrap())),
+        [command, ..] => match HELP.lines().find(|l| l.split(' ').next() == Some(command)) {
+            Some(usage) => Err(format!(
+                "usage: {}",
+                usage.split("  ").next().unwrap_or(usage)
+            )),
+            None => Err(format!("unknown command '{}', try help", command)),
+        },
+        [] => Ok(String::new()),
+    }
+}
+
+fn road(map: &RoadMap, road: &str) -> Result<RoadId, String> {
+    let road = road.parse::<RoadId>()?;
+    if map.roads.contains_key(&road) {
+        Ok(road)
+    } else {
+        Err(format!("no such road '{}'", road))
+    }
+}
+
+fn route(state: &ConsoleState, from: &str, to: &str, via: Option<&str>) -> Result<String, String> {
+    let map = state.map.lock().unwrap();
+    let endpoint = |id: &str| {
+        let id = id.parse::<IntersectionId>()?;
+        if map.intersections().any(|i| i.id() == id) {
+            Ok(id)
+        } else {
+            Err(format!("no such intersection '{}'", id))
+        }
+    };
+    let (from, to) = (endpoint(from)?, endpoint(to)?);
+    let via = via.map(|r| road(&map, r)).transpose()?;
+
+    let (cost, best) = map.best_direction(from, to, via.as_ref());
+    let (length, shortest) = map.shortest_direction(from, to, via.as_ref());
+    let describe = |road: Option<RoadId>, cost: f64| match road {
+        Some(road) if cost.is_finite() => format!("{} ({:.1})", road, cost),
+        _ => "unreachable".to_string(),
+    };
+    Ok(format!(
+        "{} to {}: best {}, shortest {}",
+        from,
+        to,
+        describe(best, cost),
+        describe(shortest, length)
+    ))
+}
+
+fn cost(state: &ConsoleState, road_id: &str) -> Result<String, String> {
+    let map = state.map.lock().unwrap();
+    let road_id = road(&map, road_id)?;
+    let road = &map.roads[&road_id];
+    let overrides = &state.snapshot.lock().unwrap().cost_overrides;
+    Ok((0..2)
+        .map(|n| {
+            let lane = LaneId(road_id, n);
+            let entry = lane.entry_id();
+            let mut line = format!(
+                "{} ({} to {}): static {:.1}, dynamic {:.1}",
+                lane,
+                entry,
+                lane.exit_id(),
+                road.cost_from(&entry, false),
+                road.cost_from(&entry, true)
+            );
+            if overrides.contains_key(&lane) {
+                line.push_str(", overridden");
+            }
+            if map.is_closed(&lane) {
+                line.push_str(", closed");
+            }
+            line
+        })
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+///`fwd` is the lane from the first intersection of the road to the second, `bck` the other
+fn close(
+    state: &ConsoleState,
+    closed: bool,
+    road_id: &str,
+    direction: Option<&str>,
+) -> Result<String, String> {
+    let road_id = road(&state.map.lock().unwrap(), road_id)?;
+    let lanes = match direction {
+        None => vec![0, 1],
+        Some("fwd") => vec![0],
+        Some("bck") => vec![1],
+        Some(d) => return Err(format!("unknown direction '{}', expected fwd or bck", d)),
+    };
+    let mut changed = vec![];
+    for n in lanes {
+        let lane = LaneId(road_id, n);
+        send(&state.control, ControlCommand::SetClosed { lane, closed })?;
+        changed.push(lane.to_string());
+    }
+    Ok(format!(
+        "{} {}",
+        if closed { "closing" } else { "opening" },
+        changed.join(" ")
+    ))
+}
+
+fn set(state: &ConsoleState, coeff: &str, value: &str) -> Result<String, String> {
+    let value = value
+        .parse::<f64>()
+        .ok()
+        .filter(|v| *v >= 0.0 && v.is_finite())
+        .ok_or_else(|| format!("invalid value '{}', expected a non-negative number", value))?;
+    let (mut density_coeff, mut vel_coeff, mut clearance_coeff, mut queue_coeff) =
+        (None, None, None, None);
+    match coeff {
+        "density" => density_coeff = Some(value),
+        "vel" => vel_coeff = Some(value),
+        "clearance" => clearance_coeff = Some(value),
+        "queue" => queue_coeff = Some(value),
+        _ => {
+            return Err(format!(
+                "unknown coefficient '{}', expected density, vel, clearance or queue",
+                coeff
+            ))
+        }
+    }
+    send(
+        &state.control,
+        ControlCommand::SetCoefficients {
+            density_coeff,
+            vel_coeff,
+            clearance_coeff,
+            queue_coeff,
+        },
+    )?;
+    Ok(format!("setting {}_coeff to {}", coeff, value))
+}
+
+fn set_led(state: &ConsoleState, led: &str, on: &str) -> Result<String, String> {
+    let led = led.parse::<Led>()?;
+    let on = match on {
+        "on" => true,
+        "off" => false,
+        _ => return Err(format!("invalid state '{}', expected on or off", on)),
+    };
+    state
+        .leds
+        .send(LedCommand::Set(led, on))
+        .map_err(|_| "the light controller has stopped".to_string())?;
+    Ok(format!(
+        "{:?} {} until the route decisions change",
+        led,
+        if on { "on" } else { "off" }
+    ))
+}
+
+fn vehicles(state: &ConsoleState, on: &str) -> Result<String, String> {
+    // lanes end in the direction, which isn't a valid road
+    let (road_id, lane) = match on.parse::<LaneId>() {
+        Ok(lane) => (lane.0, Some(lane)),
+        Err(_) => (on.parse::<RoadId>()?, None),
+    };
+    road(&state.map.lock().unwrap(), &road_id.to_string())?;
+    let snapshot = state.snapshot.lock().unwrap();
+    let mut vehicles = snapshot
+        .vehicles
+        .iter()
+        .filter(|v| v.lane.0 == road_id && lane.is_none_or(|l| v.lane == l))
+        .collect::<Vec<_>>();
+    if vehicles.is_empty() {
+        return Ok(format!("no vehicles on {}", on));
+    }
+    vehicles.sort_by_key(|v| v.id);
+    Ok(vehicles
+        .iter()
+        .map(|v| {
+            format!(
+                "{} {} on {}: {:.1} m/s at ({:.1}, {:.1})",
+                v.id, v.class, v.lane, v.speed, v.world_pos.0, v.world_pos.1
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+fn dump(snapshot: &Snapshot) -> String {
+    let mut lanes = snapshot.lanes.clone();
+    lanes.sort_by_key(|l| l.lane);
+    let dump = json!({
+        "coefficients": api::coefficients(snapshot),
+        "lanes": lanes,
+        "routes": snapshot.routes,
+        "leds": snapshot.leds.iter().map(|led| format!("{:?}", led)).collect::<Vec<_>>(),
+        "incidents": snapshot.incidents,
+        "overrides": snapshot
+            .cost_overrides
+            .iter()
+            .map(|(lane, cost)| json!({ "lane": lane, "cost": cost }))
+            .collect::<Vec<_>>(),
+    });
+    serde_json::to_string_pretty(&dump).unwrap_or_default()
+}
+
+///commands are applied by the pipeline within a cost update
+fn send(control: &Sender<ControlCommand>, command: ControlCommand) -> Result<(), String> {
+    control
+        .send(command)
+        .map_err(|_| "the pipeline has stopped".to_string())
+}
@@ -0,0 +1,236 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::map::LaneId;
+
+/// how the instantaneous cost of a lane is turned into the cost used for routing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Smoothing {
+    ///use every new cost as is
+    Raw,
+    ///exponential moving average with time constant `tau`
+    Ema { tau: Duration },
+    ///median of the costs seen over the last `window`
+    Median { window: Duration },
+    ///follows the cost, changing by at most `per_sec` each second
+    RateLimit { per_sec: f64 },
+}
+
+///written as `raw`, `ema:<secs>`, `median:<secs>` or `rate:<cost per sec>`
+impl FromStr for Smoothing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, value) = match s.split_once(':') {
+            Some((kind, value)) => (kind, Some(value)),
+            None => (s, None),
+        };
+        let value = || {
+            value
+                .ok_or_else(|| format!("'{}' needs a value, ie '{}:10'", kind, kind))?
+                .parse::<f64>()
+                .map_err(|e| format!("'{}': {}", s, e))
+                .and_then(|v| {
+                    if v > 0.0 {
+                        Ok(v)
+                    } else {
+                        Err(format!("'{}': value must be positive", s))
+                    }
+                })
+        };
+        match kind {
+            "raw" => Ok(Smoothing::Raw),
+            "ema" => Ok(Smoothing::Ema {
+                tau: Duration::from_secs_f64(value()?),
+            }),
+            "median" => Ok(Smoothing::Median {
+                window: Duration::from_secs_f64(value()?),
+            }),
+            "rate" => Ok(Smoothing::RateLimit { per_sec: value()? }),
+            _ => Err(format!(
+                "unknown smoothing '{}', expected raw, ema:<secs>, median:<secs> or rate:<per sec>",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Smoothing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Smoothing::Raw => write!(f, "raw"),
+            Smoothing::Ema { tau } => write!(f, "ema:{}", tau.as_secs_f64()),
+            Smoothing::Median { window } => write!(f, "median:{}", window.as_secs_f64()),
+            Smoothing::RateLimit { per_sec } => write!(f, "rate:{}", per_sec),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct LaneCost {
+    raw: f64,
+    smoothed: f64,
+    updated: Instant,
+    ///recent raw costs, oldest first, only kept for the median
+    samples: VecDeque<(Instant, f64)>,
+}
+
+/// smoothed dynamic cost of every lane, keeping the raw cost next to it
+pub struct CostSmoother {
+    ///smoothing of the lanes without one of their own
+    pub default: Smoothing,
+    overrides: HashMap<LaneId, Smoothing>,
+    lanes: HashMap<LaneId, LaneCost>,
+}
+
+impl CostSmoother {
+    pub fn new(default: Smoothing) -> Self {
+        Self {
+            default,
+            overrides: HashMap::new(),
+            lanes: HashMap::new(),
+        }
+    }
+
+    ///`spec` is the default smoothing followed by any per lane ones, separated by spaces,
+    ///ie `ema:5 3-4-r=median:30 1-2-l=raw`
+    pub fn from_spec(spec: &str) -> Result<Self, String> {
+        let mut smoother = Self::new(Smoothing::Raw);
+        for part in spec.split_whitespace() {
+            match part.split_once('=') {
+                Some((lane, smoothing)) => {
+                    smoother.set_smoothing(lane.parse()?, smoothing.parse()?)
+                }
+                None => smoother.default = part.parse()?,
+            }
+        }
+        Ok(smoother)
+    }
+
+    pub fn set_smoothing(&mut self, lane_id: LaneId, smoothing: Smoothing) {
+        self.overrides.insert(lane_id, smoothing);
+    }
+
+    pub fn smoothing(&self, lane_id: &LaneId) -> Smoothing {
+        *self.overrides.get(lane_id).unwrap_or(&self.default)
+    }
+
+    ///feeds a new raw cost of the lane and returns its smoothed cost
+    pub fn update(&mut self, lane_id: LaneId, raw: f64, now: Instant) -> f64 {
+        let smoothing = self.smoothing(&lane_id);
+        let lane = self.lanes.entry(lane_id).or_insert_with(|| LaneCost {
+            raw,
+            smoothed: raw,
+            updated: now,
+            samples: VecDeque::new(),
+        });
+        let dt = now.saturating_duration_since(lane.updated).as_secs_f64();
+        lane.raw = raw;
+        lane.updated = now;
+
+        lane.smoothed = match smoothing {
+            Smoothing::Raw => raw,
+            Smoothing::Ema { tau } => {
+                let alpha = (dt / tau.as_secs_f64()).min(1.0);
+                lane.smoothed + alpha * (raw - lane.smoothed)
+            }
+            Smoothing::Median { window } => {
+                lane.samples.push_back((now, raw));
+                while let Some((t, _)) = lane.samples.front() {
+                    if now.saturating_duration_since(*t) <= window {
+                        break;
+                    }
+                    lane.samples.pop_front();
+                }
+                let mut costs = lane.samples.iter().map(|(_, c)| *c).collect::<Vec<f64>>();
+                costs.sort_by(|a, b| a.total_cmp(b));
+                costs[costs.len() / 2]
+            }
+            Smoothing::RateLimit { per_sec } => {
+                let max_step = per_sec * dt;
+                lane.smoothed + (raw - lane.smoothed).clamp(-max_step, max_step)
+            }
+        };
+        if !matches!(smoothing, Smoothing::Median { .. }) {
+            lane.samples.clear();
+        }
+        lane.smoothed
+    }
+
+    ///last cost fed for the lane, before smoothing
+    pub fn raw(&self, lane_id: &LaneId) -> Option<f64> {
+        self.lanes.get(lane_id).map(|lane| lane.raw)
+    }
+
+    pub fn smoothed(&self, lane_id: &LaneId) -> Option<f64> {
+        self.lanes.get(lane_id).map(|lane| lane.smoothed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LANE: LaneId = LaneId(crate::map::RoadId(1, 4), 0);
+
+    ///feeds `costs` one second apart and returns the smoothed costs
+    fn feed(smoothing: &str, costs: &[f64]) -> Vec<f64> {
+        let mut smoother = CostSmoother::from_spec(smoothing).unwrap();
+        let start = Instant::now();
+        costs
+            .iter()
+            .enumerate()
+            .map(|(i, cost)| smoother.update(LANE, *cost, start + Duration::from_secs(i as u64)))
+            .collect()
+    }
+
+    #[test]
+    fn ema_approaches_a_step() {
+        let smoothed = feed("ema:2", &[0.0, 100.0, 100.0, 100.0]);
+        assert_eq!(smoothed, [0.0, 50.0, 75.0, 87.5]);
+        // steps longer than the time constant jump straight to the new cost
+        assert_eq!(feed("ema:0.5", &[0.0, 100.0]), [0.0, 100.0]);
+    }
+
+    #[test]
+    fn median_ignores_spikes() {
+        let smoothed = feed("median:2", &[10.0, 500.0, 12.0, 11.0, 500.0, 500.0]);
+        assert_eq!(smoothed, [10.0, 500.0, 12.0, 12.0, 12.0, 500.0]);
+    }
+
+    #[test]
+    fn rate_limits_each_step() {
+        let smoothed = feed("rate:30", &[0.0, 100.0, 100.0, 100.0, 100.0, 0.0]);
+        assert_eq!(smoothed, [0.0, 30.0, 60.0, 90.0, 100.0, 70.0]);
+    }
+
+    #[test]
+    fn lanes_can_have_their_own_smoothing() {
+        let smoother = CostSmoother::from_spec("ema:5 1-4-l=median:30 2-3-r=raw").unwrap();
+        assert_eq!(
+            smoother.smoothing(&LANE),
+            Smoothing::Median {
+                window: Duration::from_secs(30)
+            }
+        );
+        assert_eq!(
+            smoother.smoothing(&"1-4-r".parse().unwrap()),
+            Smoothing::Ema {
+                tau: Duration::from_secs(5)
+            }
+        );
+        assert_eq!(
+            smoother.smoothing(&"2-3-r".parse().unwrap()),
+            Smoothing::Raw
+        );
+        for spec in ["ema", "ema:0", "median:x", "mean:5", "1-4=raw"] {
+            assert!(CostSmoother::from_spec(spec).is_err(), "{}", spec);
+        }
+        assert_eq!(
+            "rate:2.5".parse::<Smoothing>().unwrap().to_string(),
+            "rate:2.5"
+        );
+    }
+}
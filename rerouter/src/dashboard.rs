@@ -0,0 +1,364 @@
+use log::{info, warn};
+use sdl2::event::Event;
+use sdl2::image::LoadTexture;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::TextureQuery;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::map::{IntersectionId, LaneId};
+use crate::pipeline::Pipeline;
+use crate::RxDatas;
+
+///time between two frames of the traffic map
+const FRAME_INTERVAL: Duration = Duration::from_millis(33);
+///how far ahead the dashboard shows the forecast lane costs
+const FORECAST_PREVIEW: Duration = Duration::from_secs(60);
+
+/// runs the pipeline under the SDL traffic map window until it is closed
+pub fn run(
+    pipeline: &mut Pipeline,
+    rx: &Receiver<RxDatas>,
+    is_running: &AtomicBool,
+    config: &Config,
+) {
+    let ctx = sdl2::init().unwrap();
+    let video_subsys = ctx.video().unwrap();
+    video_subsys.gl_attr().set_multisample_buffers(1);
+    video_subsys.gl_attr().set_multisample_samples(2);
+    video_subsys.gl_attr().set_accelerated_visual(true);
+    // println!("{}", video_subsys.gl_attr().context_major_version());
+
+    let window = video_subsys
+        .window("Automatic traffic router (traffic map)", 840, 480)
+        .position_centered()
+        .build()
+        .unwrap();
+    let ttf_context = sdl2::ttf::init()
+        .map_err(|e| e.to_string())
+        .expect("ttf context ( some font thing )");
+
+    let mut canvas = window.into_canvas().build().unwrap();
+    let font = ttf_context.load_font(&config.font, 14).expect("font");
+    let texture_creator = canvas.texture_creator();
+    let texture = texture_creator.load_texture(&config.map_outline).unwrap();
+
+    let mut vel_rect;
+    let mut density_rect;
+    let mut queue_rect;
+
+    let mut detector_running = true;
+    let mut next_frame = Instant::now();
+    let mut event_pump = ctx.event_pump().unwrap();
+    'running: while is_running.load(Ordering::Relaxed) {
+        canvas.set_draw_color(Color::RGBA(20, 20, 20, 200));
+        canvas.clear();
+        canvas
+            .copy(&texture, None, Some(Rect::new(0, 0, 640, 480)))
+            .expect("rendered map outline");
+        canvas.set_draw_color(Color::MAGENTA);
+        if detector_running && !pipeline.drain(rx) {
+            warn!("Vehicle detector stopped");
+            detector_running = false;
+        }
+        pipeline.run_due(Instant::now());
+
+        canvas.set_draw_color(Color::CYAN);
+        for (_, vehicle) in &pipeline.tracker.vehicles {
+            let _ = canvas.fill_rect(Rect::new(
+                vehicle.pos.0 as i32 - 5,
+                vehicle.pos.1 as i32 - 5,
+                10,
+                10,
+            ));
+
+            let lane = vehicle.lane_id;
+            let n1_id = lane.0 .0;
+            let n2_id = lane.0 .1;
+            let lane_id = lane.1;
+
+            let srf = font
+                .render(&format!(
+                    "{}-{} {}{} {}% {}",
+                    if n1_id < 10 {
+                        format!("{}", n1_id)
+                    } else {
+                        format!("{}", n1_id as u8 as char)
+                    },
+                    if n2_id < 10 {
+                        format!("{}", n2_id)
+                    } else {
+                        format!("{}", n2_id as u8 as char)
+                    },
+                    if lane_id == 1 { "right" } else { "left" },
+                    if vehicle.lane_candidates.is_empty() {
+                        ""
+                    } else {
+                        "?"
+                    },
+                    (vehicle.filter.confidence() * 100.0).round(),
+                    vehicle.vehicle_class(),
+                ))
+                .blended(Color::MAGENTA)
+                .expect("rendered text");
+            let texture = texture_creator
+                .create_texture_from_surface(srf)
+                .expect("texture");
+            let TextureQuery { width, height, .. } = texture.query();
+            let _ = canvas.copy(
+                &texture,
+                None,
+                Some(Rect::new(
+                    vehicle.pos.0 as i32 + 5,
+                    vehicle.pos.1 as i32 + 5,
+                    width,
+                    height,
+                )),
+            );
+        }
+
+        {
+            let map = pipeline.map.lock().unwrap();
+            let tracker = &pipeline.tracker;
+            let mut y = 200;
+            let mut visited = vec![];
+            for LaneId(road_id, _) in tracker.lanes.keys() {
+                if visited.contains(road_id) {
+                    continue;
+                }
+
+                let road_len = map.road_length(road_id).unwrap();
+                let road = map.roads.get(road_id).unwrap();
+                let travel_time = |lane_id| {
+                    let lane_id = LaneId(*road_id, lane_id);
+                    match tracker.travel_times.get(&lane_id) {
+                        Some(t) if t.count() > 0 => format!(
+                            "{:.1}s/{}",
+                            tracker.lane_travel_time(&lane_id).unwrap(),
+                            t.count()
+                        ),
+                        _ => "-".to_string(),
+                    }
+                };
+                let queue = |lane_id| {
+                    let q = tracker
+                        .lane_queue(&LaneId(*road_id, lane_id), road_len)
+                        .unwrap_or_default();
+                    format!("{}/{:.0}m", q.queued, q.length)
+                };
+                let dynamic_cost = |lane_id| {
+                    let lane_id = LaneId(*road_id, lane_id);
+                    match (
+                        pipeline.cost_smoother.smoothed(&lane_id),
+                        pipeline.cost_smoother.raw(&lane_id),
+                    ) {
+                        (Some(smoothed), Some(raw)) => {
+                            format!("{}/{}", smoothed.round(), raw.round())
+                        }
+                        _ => "-".to_string(),
+                    }
+                };
+                let forecast = |lane_id| match pipeline
+                    .forecaster
+                    .forecast(&LaneId(*road_id, lane_id))
+                    .and_then(|f| f.at(FORECAST_PREVIEW.as_secs_f64()))
+                {
+                    Some(cost) => format!("{}", cost.round()),
+                    None => "-".to_string(),
+                };
+                let lines = [
+                    format!(
+                        "road {}-{} cost: {}, {} dynamic/raw: {}, {}",
+                        road_id.0,
+                        road_id.1,
+                        road.cost_from(&IntersectionId(road_id.0), true).round(),
+                        road.cost_from(&IntersectionId(road_id.1), true).round(),
+                        dynamic_cost(0),
+                        dynamic_cost(1),
+                    ),
+                    format!(
+                        "time: {}, {} queue: {}, {} in {}s: {}, {}",
+                        travel_time(0),
+                        travel_time(1),
+                        queue(0),
+                        queue(1),
+                        FORECAST_PREVIEW.as_secs(),
+                        forecast(0),
+                        forecast(1)
+                    ),
+                ];
+                for line in &lines {
+                    let srf = font
+                        .render(line)
+                        .blended(Color::CYAN)
+                        .expect("rendered text");
+                    let texture = texture_creator
+                        .create_texture_from_surface(srf)
+                        .expect("texture");
+                    let TextureQuery { width, height, .. } = texture.query();
+                    let text_rect = Rect::new(820 - width as i32, y, width, height);
+                    let _ = canvas.copy(&texture, None, Some(text_rect));
+                    y += height as i32 + 2;
+                }
+                y += 3;
+                visited.push(*road_id);
+            }
+        }
+
+        let mut y = 10;
+        for incident in pipeline.incidents.incidents.values() {
+            let srf = font
+                .render(&format!("incident {}", incident))
+                .blended(Color::RED)
+                .expect("rendered text");
+            let texture = texture_creator
+                .create_texture_from_surface(srf)
+                .expect("texture");
+            let TextureQuery { width, height, .. } = texture.query();
+            let _ = canvas.copy(&texture, None, Some(Rect::new(10, y, width, height)));
+            y += height as i32 + 2;
+        }
+
+        canvas.set_draw_color(Color::WHITE);
+
+        let srf = font
+            .render(&format!(
+                "Cost model (m): {} forecast routing (f): {}",
+                pipeline.cost_model.name(),
+                if pipeline.map.lock().unwrap().use_forecast {
+                    "on"
+                } else {
+                    "off"
+                }
+            ))
+            .blended(Color::CYAN)
+            .expect("rendered text");
+        let texture = texture_creator
+            .create_texture_from_surface(srf)
+            .expect("texture");
+        let TextureQuery { width, height, .. } = texture.query();
+        let text_rect = Rect::new(820 - width as i32, 10, width, height);
+        let _ = canvas.copy(&texture, None, Some(text_rect));
+
+        let srf = font
+            .render(&format!("Speed coeff: {}", pipeline.cost_params.vel_coeff))
+            .blended(Color::CYAN)
+            .expect("rendered text");
+        let texture = texture_creator
+            .create_texture_from_surface(srf)
+            .expect("texture");
+        let TextureQuery { width, height, .. } = texture.query();
+        let text_rect = Rect::new(820 - width as i32, 50, width, height);
+        vel_rect = Rect::new(
+            text_rect.x - 5,
+            text_rect.y - 5,
+            text_rect.width() + 10,
+            text_rect.height() + 10,
+        );
+        let _ = canvas.copy(&texture, None, Some(text_rect));
+        let _ = canvas.draw_rect(vel_rect);
+
+        let srf = font
+            .render(&format!(
+                "Density coeff: {}",
+                pipeline.cost_params.density_coeff
+            ))
+            .blended(Color::CYAN)
+            .expect("rendered text");
+        let texture = texture_creator
+            .create_texture_from_surface(srf)
+            .expect("texture");
+        let TextureQuery { width, height, .. } = texture.query();
+        let text_rect = Rect::new(820 - width as i32, 100, width, height);
+        density_rect = Rect::new(
+            text_rect.x - 5,
+            text_rect.y - 5,
+            text_rect.width() + 10,
+            text_rect.height() + 10,
+        );
+        let _ = canvas.copy(&texture, None, Some(text_rect));
+        let _ = canvas.draw_rect(density_rect);
+
+        let srf = font
+            .render(&format!(
+                "Queue coeff: {}",
+                pipeline.cost_params.queue_coeff
+            ))
+            .blended(Color::CYAN)
+            .expect("rendered text");
+        let texture = texture_creator
+            .create_texture_from_surface(srf)
+            .expect("texture");
+        let TextureQuery { width, height, .. } = texture.query();
+        let text_rect = Rect::new(820 - width as i32, 150, width, height);
+        queue_rect = Rect::new(
+            text_rect.x - 5,
+            text_rect.y - 5,
+            text_rect.width() + 10,
+            text_rect.height() + 10,
+        );
+        let _ = canvas.copy(&texture, None, Some(text_rect));
+        let _ = canvas.draw_rect(queue_rect);
+
+        let mouse_state = event_pump.mouse_state();
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => {
+                    is_running.store(false, Ordering::Relaxed);
+                    break 'running;
+                }
+
+                Event::KeyDown {
+                    keycode: Some(Keycode::M),
+                    ..
+                } => {
+                    let name = traffic_cost::next_model_name(pipeline.cost_model.name());
+                    pipeline.cost_model = traffic_cost::from_name(name).unwrap();
+                    info!("Switched to the '{}' cost model", name);
+                }
+
+                Event::KeyDown {
+                    keycode: Some(Keycode::F),
+                    ..
+                } => {
+                    let mut map = pipeline.map.lock().unwrap();
+                    map.use_forecast = !map.use_forecast;
+                }
+
+                Event::MouseWheel { y, .. } => {
+                    let cost_params = &mut pipeline.cost_params;
+                    if vel_rect.contains_point((mouse_state.x(), mouse_state.y())) {
+                        cost_params.vel_coeff += (y.signum() * y * y) as f64;
+                    }
+                    if density_rect.contains_point((mouse_state.x(), mouse_state.y())) {
+                        cost_params.density_coeff += (y.signum() * y * y) as f64;
+                    }
+                    if queue_rect.contains_point((mouse_state.x(), mouse_state.y())) {
+                        cost_params.queue_coeff += (y.signum() * y * y) as f64;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        canvas.present();
+
+        next_frame += FRAME_INTERVAL;
+        let now = Instant::now();
+        if next_frame > now {
+            thread::sleep(next_frame - now);
+        } else {
+            next_frame = now;
+        }
+    }
+}
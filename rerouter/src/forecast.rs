@@ -0,0 +1,275 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::map::LaneId;
+use crate::vehicle_tracker::LaneTransition;
+
+///interval the cost history is sampled at, also the spacing of the forecast points
+pub const FORECAST_STEP: Duration = Duration::from_secs(10);
+///number of steps forecast ahead
+const FORECAST_STEPS: usize = 18;
+///samples kept per lane for fitting
+const HISTORY_LEN: usize = 90;
+///fewer samples than this are only extrapolated with a linear trend
+const MIN_FIT_SAMPLES: usize = 12;
+///samples the linear trend is fitted over
+const TREND_SAMPLES: usize = 6;
+///keeps the fitted cost dynamics from blowing up over the forecast horizon
+const MAX_PERSISTENCE: f64 = 0.98;
+
+/// predicted dynamic costs of a lane, `costs[i]` is the cost `i * step` from when it was made
+#[derive(Debug, Clone, PartialEq)]
+pub struct CostForecast {
+    pub step: Duration,
+    pub costs: Vec<f64>,
+}
+
+impl CostForecast {
+    ///cost `secs` ahead, linearly interpolated and held at the last point beyond the horizon
+    pub fn at(&self, secs: f64) -> Option<f64> {
+        let pos = (secs / self.step.as_secs_f64()).max(0.0);
+        let i = pos.floor() as usize;
+        match (self.costs.get(i), self.costs.get(i + 1)) {
+            (Some(a), Some(b)) => Some(a + (b - a) * pos.fract()),
+            (Some(a), None) => Some(*a),
+            _ => self.costs.last().copied(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    cost: f64,
+    ///vehicles that came onto the lane from other roads during the step
+    inflow: f64,
+}
+
+#[derive(Debug, Default)]
+struct LaneHistory {
+    samples: VecDeque<Sample>,
+    latest_cost: Option<f64>,
+    inflow: usize,
+}
+
+/// per lane short-term cost forecasts from an autoregressive model with the upstream inflow as input:
+/// `cost[k+1] = a + b * cost[k] + g * inflow[k]`
+///
+/// the model is refitted on the recent history of each lane at every step, lanes with too little
+/// history are extrapolated along their recent trend instead
+pub struct CostForecaster {
+    lanes: HashMap<LaneId, LaneHistory>,
+    last_sample: Instant,
+    pub forecasts: HashMap<LaneId, CostForecast>,
+}
+
+impl CostForecaster {
+    pub fn new() -> Self {
+        Self {
+            lanes: HashMap::new(),
+            last_sample: Instant::now(),
+            forecasts: HashMap::new(),
+        }
+    }
+
+    ///latest dynamic cost of the lane, sampled at the next step
+    pub fn observe(&mut self, lane_id: LaneId, cost: f64) {
+        self.lanes.entry(lane_id).or_default().latest_cost = Some(cost);
+    }
+
+    ///counts vehicles coming onto lanes from other roads
+    pub fn record_transitions(&mut self, transitions: &[LaneTransition]) {
+        for transition in transitions.iter().filter(|t| t.from.is_some()) {
+            self.lanes.entry(transition.to).or_default().inflow += 1;
+        }
+    }
+
+    ///samples every lane and refits the forecasts once a step has passed, returns true if it did
+    pub fn update(&mut self, now: Instant) -> bool {
+        if now.saturating_duration_since(self.last_sample) < FORECAST_STEP {
+            return false;
+        }
+        self.last_sample = now;
+
+        for (lane_id, lane) in self.lanes.iter_mut() {
+            let cost = match lane.latest_cost {
+                Some(cost) => cost,
+                None => continue,
+            };
+            lane.samples.push_back(Sample {
+                cost,
+                inflow: lane.inflow as f64,
+            });
+            lane.inflow = 0;
+            if lane.samples.len() > HISTORY_LEN {
+                lane.samples.pop_front();
+            }
+
+            let samples = lane.samples.iter().copied().collect::<Vec<Sample>>();
+            let costs = match fit_arx(&samples) {
+                Some(model) => model.forecast(&samples),
+                None => trend_forecast(&samples),
+            };
+            self.forecasts.insert(
+                *lane_id,
+                CostForecast {
+                    step: FORECAST_STEP,
+                    costs,
+                },
+            );
+        }
+        true
+    }
+
+    pub fn forecast(&self, lane_id: &LaneId) -> Option<&CostForecast> {
+        self.forecasts.get(lane_id)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ArxModel {
+    a: f64,
+    b: f64,
+    g: f64,
+}
+
+impl ArxModel {
+    ///the inflow is held at its last value over the horizon
+    fn forecast(&self, samples: &[Sample]) -> Vec<f64> {
+        let last = samples.last().unwrap();
+        let mut costs = vec![last.cost];
+        let mut cost = last.cost;
+        for _ in 0..FORECAST_STEPS {
+            cost = (self.a + self.b * cost + self.g * last.inflow).max(0.0);
+            costs.push(cost);
+        }
+        costs
+    }
+}
+
+///least squares fit of the autoregressive model, `None` with too few or degenerate samples
+fn fit_arx(samples: &[Sample]) -> Option<ArxModel> {
+    if samples.len() < MIN_FIT_SAMPLES {
+        return None;
+    }
+    // normal equations of y = [1, cost, inflow] . [a, b, g]
+    let mut ata = [[0.0; 3]; 3];
+    let mut aty = [0.0; 3];
+    for pair in samples.windows(2) {
+        let x = [1.0, pair[0].cost, pair[0].inflow];
+        for i in 0..3 {
+            aty[i] += x[i] * pair[1].cost;
+            for j in 0..3 {
+                ata[i][j] += x[i] * x[j];
+            }
+        }
+    }
+    let [a, b, g] = solve3(ata, aty)?;
+    Some(ArxModel {
+        a,
+        b: b.clamp(-MAX_PERSISTENCE, MAX_PERSISTENCE),
+        g,
+    })
+}
+
+///cramer's rule, `None` if the system is singular
+fn solve3(m: [[f64; 3]; 3], y: [f64; 3]) -> Option<[f64; 3]> {
+    let det = |m: &[[f64; 3]; 3]| {
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    };
+    let d = det(&m);
+    if d.abs() < 1e-9 {
+        return None;
+    }
+    let mut x = [0.0; 3];
+    for (col, value) in x.iter_mut().enumerate() {
+        let mut mc = m;
+        for (row, r) in mc.iter_mut().enumerate() {
+            r[col] = y[row];
+        }
+        *value = det(&mc) / d;
+    }
+    Some(x)
+}
+
+///extrapolates the slope of the last few samples
+fn trend_forecast(samples: &[Sample]) -> Vec<f64> {
+    let recent = &samples[samples.len().saturating_sub(TREND_SAMPLES)..];
+    let last = recent.last().map(|s| s.cost).unwrap_or(0.0);
+    let slope = if recent.len() > 1 {
+        (last - recent[0].cost) / (recent.len() - 1) as f64
+    } else {
+        0.0
+    };
+    (0..=FORECAST_STEPS)
+        .map(|i| (last + slope * i as f64).max(0.0))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///`cost[k+1] = 20 + 0.6 * cost[k] + 3 * inflow[k]` with a varying inflow
+    fn arx_series(len: usize) -> Vec<Sample> {
+        let mut samples = vec![];
+        let mut cost = 10.0;
+        for k in 0..len {
+            let inflow = ((k * 7) % 5) as f64;
+            samples.push(Sample { cost, inflow });
+            cost = 20.0 + 0.6 * cost + 3.0 * inflow;
+        }
+        samples
+    }
+
+    #[test]
+    fn fit_recovers_the_model() {
+        let model = fit_arx(&arx_series(40)).unwrap();
+        assert!((model.a - 20.0).abs() < 1e-6);
+        assert!((model.b - 0.6).abs() < 1e-6);
+        assert!((model.g - 3.0).abs() < 1e-6);
+
+        // held inflow settles on the steady state of the model
+        let samples = arx_series(40);
+        let costs = model.forecast(&samples);
+        assert_eq!(costs.len(), FORECAST_STEPS + 1);
+        assert_eq!(costs[0], samples.last().unwrap().cost);
+        let steady = (20.0 + 3.0 * samples.last().unwrap().inflow) / (1.0 - 0.6);
+        assert!((costs[FORECAST_STEPS] - steady).abs() < 0.01);
+    }
+
+    #[test]
+    fn short_or_flat_histories_are_not_fitted() {
+        assert!(fit_arx(&arx_series(MIN_FIT_SAMPLES - 1)).is_none());
+        let flat = vec![
+            Sample {
+                cost: 50.0,
+                inflow: 0.0
+            };
+            MIN_FIT_SAMPLES
+        ];
+        assert!(fit_arx(&flat).is_none());
+    }
+
+    #[test]
+    fn trend_is_extrapolated_and_floored() {
+        let rising = [10.0, 12.0, 14.0].map(|cost| Sample { cost, inflow: 0.0 });
+        let costs = trend_forecast(&rising);
+        assert_eq!(&costs[..3], [14.0, 16.0, 18.0]);
+        let falling = [10.0, 5.0, 0.0].map(|cost| Sample { cost, inflow: 0.0 });
+        assert!(trend_forecast(&falling).iter().all(|c| *c == 0.0));
+    }
+
+    #[test]
+    fn forecast_is_interpolated() {
+        let forecast = CostForecast {
+            step: FORECAST_STEP,
+            costs: vec![10.0, 20.0, 40.0],
+        };
+        assert_eq!(forecast.at(0.0), Some(10.0));
+        assert_eq!(forecast.at(5.0), Some(15.0));
+        assert_eq!(forecast.at(15.0), Some(30.0));
+        assert_eq!(forecast.at(1000.0), Some(40.0));
+    }
+}
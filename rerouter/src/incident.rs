@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::map::{LaneId, RoadMap};
+use crate::queue::STOPPED_SPEED;
+use crate::vehicle_tracker::Tracker;
+
+///a vehicle stopped away from the queue for this long is reported as stalled
+const STALL_TIME: Duration = Duration::from_secs(20);
+///stopped vehicles closer than this (m) behind the end of the queue are part of it
+const MIDLANE_MARGIN: f64 = 5.0;
+///lane speed below this share of its usual speed counts as a sudden drop
+const SPEED_DROP_RATIO: f64 = 0.4;
+///usual lane speeds (m/s) below this are too slow to detect a drop from
+const MIN_BASELINE_SPEED: f64 = 1.0;
+///time constant of the usual lane speed average
+const BASELINE_TAU: Duration = Duration::from_secs(120);
+///a lane that has been empty this long while an upstream lane is queued up is likely blocked
+const EMPTY_TIME: Duration = Duration::from_secs(30);
+///upstream queues of at least this many vehicles count as full
+const UPSTREAM_FULL_QUEUE: usize = 3;
+///an incident clears once its condition has not been seen for this long
+const CLEAR_TIME: Duration = Duration::from_secs(10);
+///cost added to a lane per severity level of each active incident on it
+const INCIDENT_PENALTY: f64 = 100.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum IncidentKind {
+    StalledVehicle,
+    SpeedDrop,
+    BlockedLane,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub enum Severity {
+    Minor = 1,
+    Major = 2,
+    Critical = 3,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Incident {
+    pub id: u64,
+    pub kind: IncidentKind,
+    pub lane: LaneId,
+    ///map coordinates (meters) of the incident, if it can be pinned down
+    pub location: Option<(f64, f64)>,
+    pub severity: Severity,
+    #[serde(skip)]
+    pub raised: Instant,
+    #[serde(skip)]
+    last_seen: Instant,
+}
+
+impl Incident {
+    pub fn penalty(&self) -> f64 {
+        INCIDENT_PENALTY * self.severity as u8 as f64
+    }
+}
+
+impl fmt::Display for Incident {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "#{} {:?} ({:?}) on {}-{} {}",
+            self.id,
+            self.kind,
+            self.severity,
+            self.lane.0 .0,
+            self.lane.0 .1,
+            if self.lane.1 == 1 { "right" } else { "left" },
+        )?;
+        if let Some((x, y)) = self.location {
+            write!(f, " at ({:.1}, {:.1})", x, y)?;
+        }
+        Ok(())
+    }
+}
+
+/// detects stalled vehicles, sudden lane speed drops and blocked lanes from tracker data
+pub struct IncidentDetector {
+    pub incidents: HashMap<(IncidentKind, LaneId), Incident>,
+    speed_baseline: HashMap<LaneId, f64>,
+    empty_since: HashMap<LaneId, Instant>,
+    last_update: Instant,
+    next_id: u64,
+}
+
+impl IncidentDetector {
+    pub fn new() -> Self {
+        Self {
+            incidents: HashMap::new(),
+            speed_baseline: HashMap::new(),
+            empty_since: HashMap::new(),
+            last_update: Instant::now(),
+            next_id: 1,
+        }
+    }
+
+    ///checks every lane for incidents, raising new ones and clearing the ones no longer seen
+    pub fn update(&mut self, tracker: &Tracker, map: &RoadMap) {
+        let now = Instant::now();
+        let dt = now.duration_since(self.last_update).as_secs_f64();
+        self.last_update = now;
+
+        let mut seen = vec![];
+        for (lane_id, vehicles) in &tracker.lanes {
+            let road_length = match map.road_length(&lane_id.0) {
+                Some(length) => length,
+                None => continue,
+            };
+            let queue = tracker.lane_queue(lane_id, road_length).unwrap_or_default();
+            // the queue and positions along the lane are measured on the lane geometry if any
+            let lane_length = tracker.lane_length(lane_id, road_length);
+
+            // stalled vehicle: stopped away from the queue at the end of the lane for too long
+            for vehicle in vehicles.iter().filter_map(|id| tracker.vehicles.get(id)) {
+                if vehicle.stopped_for() < STALL_TIME {
+                    continue;
+                }
+                let midlane = match vehicle.lane_pos {
+                    Some(pos) => lane_length - pos > queue.length + MIDLANE_MARGIN,
+                    // without positions along the lane, look for traffic moving past it
+                    None => vehicles
+                        .iter()
+                        .filter_map(|id| tracker.vehicles.get(id))
+                        .any(|v| v.avg_vel > STOPPED_SPEED),
+                };
+                if midlane {
+                    let severity = if vehicle.stopped_for() > STALL_TIME * 3 {
+                        Severity::Major
+                    } else {
+                        Severity::Minor
+                    };
+                    seen.push((
+                        IncidentKind::StalledVehicle,
+                        *lane_id,
+                        Some(vehicle.world_pos),
+                        severity,
+                    ));
+                }
+            }
+
+            // sudden speed drop compared to the usual speed of the lane
+            let speed_drop = self
+                .incidents
+                .contains_key(&(IncidentKind::SpeedDrop, *lane_id));
+            if let Some(speed) = tracker.lane_avg_speed(lane_id) {
+                let baseline = *self.speed_baseline.entry(*lane_id).or_insert(speed);
+                if baseline > MIN_BASELINE_SPEED && speed < baseline * SPEED_DROP_RATIO {
+                    let severity = if speed < baseline * SPEED_DROP_RATIO / 2.0 {
+                        Severity::Major
+                    } else {
+                        Severity::Minor
+                    };
+                    seen.push((IncidentKind::SpeedDrop, *lane_id, None, severity));
+                } else if !speed_drop {
+                    // the usual speed is left alone during a drop so it does not drift down to it
+                    let alpha = (dt / BASELINE_TAU.as_secs_f64()).min(1.0);
+                    self.speed_baseline
+                        .insert(*lane_id, baseline + alpha * (speed - baseline));
+                }
+            }
+
+            // blocked lane: nothing comes through while traffic queues up before it
+            if !vehicles.is_empty() {
+                self.empty_since.remove(lane_id);
+                continue;
+            }
+            let empty_since = *self.empty_since.entry(*lane_id).or_insert(now);
+            if now.duration_since(empty_since) < EMPTY_TIME {
+                continue;
+            }
+            let upstream_full = tracker.lanes.keys().any(|upstream| {
+                upstream.0 != lane_id.0
+                    && upstream.exit_id() == lane_id.entry_id()
+                    && map
+                        .road_length(&upstream.0)
+                        .and_then(|length| tracker.lane_queue(upstream, length))
+                        .map(|q| q.queued >= UPSTREAM_FULL_QUEUE)
+                        .unwrap_or(false)
+            });
+            if upstream_full {
+                seen.push((
+                    IncidentKind::BlockedLane,
+                    *lane_id,
+                    None,
+                    Severity::Critical,
+                ));
+            }
+        }
+
+        for (kind, lane, location, severity) in seen {
+            let incident = self.incidents.entry((kind, lane)).or_insert_with(|| {
+                let incident = Incident {
+                    id: self.next_id,
+                    kind,
+                    lane,
+                    location,
+                    severity,
+                    raised: now,
+                    last_seen: now,
+                };
+                self.next_id += 1;
+                warn!("Incident raised: {}", incident);
+                incident
+            });
+            incident.location = location.or(incident.location);
+            incident.severity = incident.severity.max(severity);
+            incident.last_seen = now;
+        }
+
+        self.incidents.retain(|_, incident| {
+            let active = now.duration_since(incident.last_seen) < CLEAR_TIME;
+            if !active {
+                info!(
+                    "Incident cleared after {}s: {}",
+                    incident.raised.elapsed().as_secs(),
+                    incident
+                );
+            }
+            active
+        });
+    }
+
+    ///cost added to the lane for the incidents currently active on it
+    pub fn penalty(&self, lane_id: &LaneId) -> f64 {
+        self.incidents
+            .values()
+            .filter(|incident| incident.lane == *lane_id)
+            .map(|incident| incident.penalty())
+            .sum()
+    }
+}
@@ -0,0 +1,220 @@
+use std::time::Instant;
+
+///white noise acceleration of the constant velocity model, (m/s²)²
+const ACCEL_NOISE: f64 = 4.0;
+///variance of a detected position, m²
+const POS_NOISE: f64 = 0.05;
+///variance of the speed reported by the detector, (m/s)²
+const SPEED_NOISE: f64 = 1.0;
+///initial velocity variance of a new track, (m/s)²
+const INIT_VEL_VAR: f64 = 25.0;
+
+type Mat4 = [[f64; 4]; 4];
+
+/// constant velocity kalman filter over the state `[x, y, vx, vy]` in map coordinates
+#[derive(Debug, Clone)]
+pub struct MotionFilter {
+    x: [f64; 4],
+    p: Mat4,
+    last_predict: Instant,
+}
+
+impl MotionFilter {
+    pub fn new(pos: (f64, f64)) -> Self {
+        let mut p = [[0.0; 4]; 4];
+        p[0][0] = POS_NOISE;
+        p[1][1] = POS_NOISE;
+        p[2][2] = INIT_VEL_VAR;
+        p[3][3] = INIT_VEL_VAR;
+        Self {
+            x: [pos.0, pos.1, 0.0, 0.0],
+            p,
+            last_predict: Instant::now(),
+        }
+    }
+
+    ///advances the state to `now`, used both before a correction and to coast through missed detections
+    pub fn predict_to(&mut self, now: Instant) {
+        let dt = now
+            .saturating_duration_since(self.last_predict)
+            .as_secs_f64();
+        self.last_predict = now;
+        if dt <= 0.0 {
+            return;
+        }
+
+        self.x[0] += self.x[2] * dt;
+        self.x[1] += self.x[3] * dt;
+
+        // P = F P Fᵀ + Q, F = [[I, dt I], [0, I]]
+        let p = self.p;
+        let mut fp = p;
+        for j in 0..4 {
+            fp[0][j] = p[0][j] + dt * p[2][j];
+            fp[1][j] = p[1][j] + dt * p[3][j];
+        }
+        let mut fpf = fp;
+        for row in fpf.iter_mut() {
+            row[0] += dt * row[2];
+            row[1] += dt * row[3];
+        }
+
+        let (q_pp, q_pv, q_vv) = (
+            ACCEL_NOISE * dt.powi(3) / 3.0,
+            ACCEL_NOISE * dt.powi(2) / 2.0,
+            ACCEL_NOISE * dt,
+        );
+        for axis in 0..2 {
+            fpf[axis][axis] += q_pp;
+            fpf[axis][axis + 2] += q_pv;
+            fpf[axis + 2][axis] += q_pv;
+            fpf[axis + 2][axis + 2] += q_vv;
+        }
+        self.p = fpf;
+    }
+
+    ///corrects the state with a measured position
+    pub fn correct_position(&mut self, z: (f64, f64)) {
+        let y = [z.0 - self.x[0], z.1 - self.x[1]];
+        let s = [
+            [self.p[0][0] + POS_NOISE, self.p[0][1]],
+            [self.p[1][0], self.p[1][1] + POS_NOISE],
+        ];
+        let det = s[0][0] * s[1][1] - s[0][1] * s[1][0];
+        if det.abs() < f64::EPSILON {
+            return;
+        }
+        let s_inv = [
+            [s[1][1] / det, -s[0][1] / det],
+            [-s[1][0] / det, s[0][0] / det],
+        ];
+
+        // K = P Hᵀ S⁻¹, H selects the position
+        let mut k = [[0.0; 2]; 4];
+        for (i, row) in k.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.p[i][0] * s_inv[0][j] + self.p[i][1] * s_inv[1][j];
+            }
+        }
+
+        for (i, row) in k.iter().enumerate() {
+            self.x[i] += row[0] * y[0] + row[1] * y[1];
+        }
+        let p = self.p;
+        for (i, row) in k.iter().enumerate() {
+            for (j, value) in self.p[i].iter_mut().enumerate() {
+                *value = p[i][j] - (row[0] * p[0][j] + row[1] * p[1][j]);
+            }
+        }
+    }
+
+    ///corrects the velocity magnitude with the speed reported by the detector,
+    ///skipped until the filter has a heading to project it on
+    pub fn correct_speed(&mut self, speed: f64) {
+        let est = self.speed();
+        if est < 1e-3 {
+            return;
+        }
+        let h = [0.0, 0.0, self.x[2] / est, self.x[3] / est];
+        let ph = self
+            .p
+            .map(|row| row.iter().zip(h).map(|(p, h)| p * h).sum::<f64>());
+        let s = h.iter().zip(ph).map(|(h, ph)| h * ph).sum::<f64>() + SPEED_NOISE;
+        let y = speed - est;
+
+        for (x, ph_i) in self.x.iter_mut().zip(ph) {
+            *x += ph_i / s * y;
+        }
+        for (row, ph_i) in self.p.iter_mut().zip(ph) {
+            for (value, ph_j) in row.iter_mut().zip(ph) {
+                *value -= ph_i * ph_j / s;
+            }
+        }
+    }
+
+    pub fn pos(&self) -> (f64, f64) {
+        (self.x[0], self.x[1])
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.x[2].hypot(self.x[3])
+    }
+
+    ///direction of travel in radians, measured from the map x axis
+    pub fn heading(&self) -> f64 {
+        self.x[3].atan2(self.x[2])
+    }
+
+    ///standard deviation of the position estimate along its worst axis, in meters
+    pub fn pos_std(&self) -> f64 {
+        self.p[0][0].max(self.p[1][1]).sqrt()
+    }
+
+    ///standard deviation of the velocity estimate along its worst axis, in m/s
+    pub fn vel_std(&self) -> f64 {
+        self.p[2][2].max(self.p[3][3]).sqrt()
+    }
+
+    ///0..1, approaches 1 as the position and velocity estimates settle
+    pub fn confidence(&self) -> f64 {
+        1.0 / (1.0 + self.pos_std() + self.vel_std())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    const STEP: Duration = Duration::from_millis(100);
+
+    ///a filter fed 5 s of detections of a vehicle driving along the y axis at `speed` m/s
+    fn tracked(speed: f64) -> (MotionFilter, Instant) {
+        let mut filter = MotionFilter::new((10.0, 0.0));
+        let mut now = filter.last_predict;
+        for i in 1..=50 {
+            now += STEP;
+            filter.predict_to(now);
+            filter.correct_position((10.0, speed * STEP.as_secs_f64() * i as f64));
+        }
+        (filter, now)
+    }
+
+    #[test]
+    fn converges_on_constant_velocity() {
+        let (filter, _) = tracked(5.0);
+        assert!((filter.speed() - 5.0).abs() < 0.1);
+        assert!((filter.heading() - std::f64::consts::FRAC_PI_2).abs() < 0.02);
+        let (x, y) = filter.pos();
+        assert!((x - 10.0).abs() < 0.05 && (y - 25.0).abs() < 0.05);
+        assert!(filter.vel_std() < 1.0);
+        assert!(filter.confidence() > MotionFilter::new((0.0, 0.0)).confidence());
+    }
+
+    #[test]
+    fn coasts_through_missed_detections() {
+        let (mut filter, now) = tracked(5.0);
+        let pos_std = filter.pos_std();
+        filter.predict_to(now + Duration::from_secs(1));
+        assert!((filter.pos().1 - 30.0).abs() < 0.2);
+        assert!(filter.pos_std() > pos_std);
+        // predicting to an earlier time leaves the state alone
+        filter.predict_to(now);
+        assert!((filter.pos().1 - 30.0).abs() < 0.2);
+    }
+
+    #[test]
+    fn speed_corrections_need_a_heading() {
+        let mut filter = MotionFilter::new((0.0, 0.0));
+        filter.correct_speed(10.0);
+        assert_eq!(filter.speed(), 0.0);
+
+        let (mut filter, _) = tracked(5.0);
+        for _ in 0..20 {
+            filter.correct_speed(6.0);
+        }
+        assert!(filter.speed() > 5.5);
+        assert!((filter.heading() - std::f64::consts::FRAC_PI_2).abs() < 0.02);
+    }
+}
@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+use crate::map::{LaneId, RoadId, RoadMap};
+
+///something that can tell which lane a detected position lies on
+pub trait LaneLocator {
+    fn lane_ids(&self) -> Vec<LaneId>;
+
+    ///how strongly the position (given both in camera pixels and map meters) belongs to `lane`,
+    ///from 0 (not on the lane) to 1
+    fn membership(&self, lane: &LaneId, pixel_pos: (f64, f64), world_pos: (f64, f64)) -> f64;
+
+    ///direction of travel on `lane` near `world_pos` in radians, if the locator knows it
+    fn direction(&self, lane: &LaneId, world_pos: (f64, f64)) -> Option<f64>;
+
+    ///distance in meters from the start of `lane` to the projection of `world_pos` on it
+    fn distance_along(&self, lane: &LaneId, world_pos: (f64, f64)) -> Option<f64>;
+
+    ///length in meters of the line `distance_along` is measured on
+    fn lane_length(&self, lane: &LaneId) -> Option<f64>;
+}
+
+#[derive(Debug, Clone)]
+pub enum LaneShape {
+    ///centerline in the direction of travel and the total lane width
+    Polyline { points: Vec<(f64, f64)>, width: f64 },
+    ///outline starting with the entry edge, the exit edge is the one halfway round
+    Polygon(Vec<(f64, f64)>),
+}
+
+impl LaneShape {
+    ///1 on the centerline of a polyline lane, falling to 0 at its edges; 1 anywhere inside a polygon
+    pub fn membership(&self, p: (f64, f64)) -> f64 {
+        match self {
+            LaneShape::Polyline { points, width } => {
+                let projection = project_on_polyline(points, p);
+                (1.0 - projection.dist / (width / 2.0)).max(0.0)
+            }
+            LaneShape::Polygon(points) => {
+                if point_in_polygon(points, p) {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+
+    ///line the along-lane distance is measured on
+    pub fn centerline(&self) -> Vec<(f64, f64)> {
+        match self {
+            LaneShape::Polyline { points, .. } => points.clone(),
+            LaneShape::Polygon(points) => {
+                let n = points.len();
+                let mid = |a: (f64, f64), b: (f64, f64)| ((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0);
+                vec![
+                    mid(points[0], points[1]),
+                    mid(points[n / 2], points[(n / 2 + 1) % n]),
+                ]
+            }
+        }
+    }
+
+    pub fn distance_along(&self, p: (f64, f64)) -> f64 {
+        project_on_polyline(&self.centerline(), p).along
+    }
+
+    pub fn direction(&self, p: (f64, f64)) -> f64 {
+        project_on_polyline(&self.centerline(), p).heading
+    }
+
+    pub fn length(&self) -> f64 {
+        self.centerline()
+            .windows(2)
+            .map(|w| (w[1].0 - w[0].0).hypot(w[1].1 - w[0].1))
+            .sum()
+    }
+}
+
+struct Projection {
+    ///distance along the polyline to the closest point on it
+    along: f64,
+    ///distance from the point to the polyline
+    dist: f64,
+    ///heading of the closest segment in radians
+    heading: f64,
+}
+
+fn project_on_polyline(points: &[(f64, f64)], p: (f64, f64)) -> Projection {
+    let mut best = Projection {
+        along: 0.0,
+        dist: f64::INFINITY,
+        heading: 0.0,
+    };
+    let mut travelled = 0.0;
+    for w in points.windows(2) {
+        let (a, b) = (w[0], w[1]);
+        let seg = (b.0 - a.0, b.1 - a.1);
+        let seg_len = seg.0.hypot(seg.1);
+        let t = if seg_len > 0.0 {
+            (((p.0 - a.0) * seg.0 + (p.1 - a.1) * seg.1) / (seg_len * seg_len)).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let closest = (a.0 + t * seg.0, a.1 + t * seg.1);
+        let dist = (p.0 - closest.0).hypot(p.1 - closest.1);
+        if dist < best.dist {
+            best = Projection {
+                along: travelled + t * seg_len,
+                dist,
+                heading: seg.1.atan2(seg.0),
+            };
+        }
+        travelled += seg_len;
+    }
+    best
+}
+
+///even-odd ray casting
+fn point_in_polygon(points: &[(f64, f64)], p: (f64, f64)) -> bool {
+    let mut inside = false;
+    let mut j = points.len() - 1;
+    for i in 0..points.len() {
+        let (a, b) = (points[i], points[j]);
+        if (a.1 > p.1) != (b.1 > p.1) && p.0 < (b.0 - a.0) * (p.1 - a.1) / (b.1 - a.1) + a.0 {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+/// lane shapes in map coordinates (meters), loaded from a text file with one lane per line:
+/// ```text
+/// lane <a> <b> <l|r> width <w> polyline <x1> <y1> <x2> <y2> ...
+/// lane <a> <b> <l|r> polygon <x1> <y1> <x2> <y2> <x3> <y3> ...
+/// ```
+/// using the same `<a>-<b>-<l|r>` naming as the road mask files, every road has to be on `map`
+pub struct LaneGeometry {
+    pub lanes: HashMap<LaneId, LaneShape>,
+}
+
+impl LaneGeometry {
+    pub fn load<P: AsRef<Path>>(path: P, map: &RoadMap) -> Result<Self, io::Error> {
+        Self::parse(&fs::read_to_string(path)?, map)
+    }
+
+    fn parse(contents: &str, map: &RoadMap) -> Result<Self, io::Error> {
+        let mut lanes = HashMap::new();
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (lane_id, shape) = parse_lane(line).ok_or_else(|| {
+                io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("line {}: invalid lane '{}'", n + 1, line),
+                )
+            })?;
+            if !map.roads.contains_key(&lane_id.0) {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("line {}: no road {} on the map", n + 1, lane_id.0),
+                ));
+            }
+            lanes.insert(lane_id, shape);
+        }
+        Ok(Self { lanes })
+    }
+}
+
+fn parse_lane(line: &str) -> Option<(LaneId, LaneShape)> {
+    let words = line.split_whitespace().collect::<Vec<&str>>();
+    if words.len() < 5 || words[0] != "lane" {
+        return None;
+    }
+    let road_id = RoadId(words[1].parse().ok()?, words[2].parse().ok()?);
+    let lane_id = match words[3] {
+        "l" => LaneId(road_id, 0),
+        "r" => LaneId(road_id, 1),
+        _ => return None,
+    };
+
+    let (width, rest) = if words[4] == "width" {
+        (Some(words.get(5)?.parse::<f64>().ok()?), &words[6..])
+    } else {
+        (None, &words[4..])
+    };
+    let (kind, coords) = rest.split_first()?;
+    let coords = coords
+        .iter()
+        .map(|w| w.parse::<f64>().ok())
+        .collect::<Option<Vec<f64>>>()?;
+    if coords.len() % 2 != 0 {
+        return None;
+    }
+    let points = coords
+        .chunks(2)
+        .map(|c| (c[0], c[1]))
+        .collect::<Vec<(f64, f64)>>();
+
+    let shape = match (*kind, width) {
+        ("polyline", Some(width)) if points.len() >= 2 => LaneShape::Polyline { points, width },
+        ("polygon", None) if points.len() >= 3 => LaneShape::Polygon(points),
+        _ => return None,
+    };
+    Some((lane_id, shape))
+}
+
+impl LaneLocator for LaneGeometry {
+    fn lane_ids(&self) -> Vec<LaneId> {
+        self.lanes.keys().copied().collect()
+    }
+
+    fn membership(&self, lane: &LaneId, _pixel_pos: (f64, f64), world_pos: (f64, f64)) -> f64 {
+        self.lanes
+            .get(lane)
+            .map(|shape| shape.membership(world_pos))
+            .unwrap_or(0.0)
+    }
+
+    fn direction(&self, lane: &LaneId, world_pos: (f64, f64)) -> Option<f64> {
+        self.lanes.get(lane).map(|shape| shape.direction(world_pos))
+    }
+
+    fn distance_along(&self, lane: &LaneId, world_pos: (f64, f64)) -> Option<f64> {
+        self.lanes
+            .get(lane)
+            .map(|shape| shape.distance_along(world_pos))
+    }
+
+    fn lane_length(&self, lane: &LaneId) -> Option<f64> {
+        self.lanes.get(lane).map(|shape| shape.length())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets;
+
+    #[test]
+    fn roads_must_be_on_the_map() {
+        let map = presets::create_map();
+        let lanes = "lane 1 2 l width 3.5 polyline 0 0 36 0";
+        let geometry = LaneGeometry::parse(lanes, &map).unwrap();
+        assert!(geometry.lanes.contains_key(&LaneId(RoadId(1, 2), 0)));
+
+        let err = LaneGeometry::parse(
+            &format!("{}\nlane 1 3 r width 3.5 polyline 0 0 9 0", lanes),
+            &map,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with("line 2:"));
+    }
+}
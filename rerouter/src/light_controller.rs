@@ -1,9 +1,14 @@
+use log::{debug, error, info, warn};
 use serial2::SerialPort;
 use std::collections::HashMap;
 use std::fmt::Error;
 use std::hash::Hash;
 use std::io::Write;
-use std::time::Duration;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::telemetry::Telemetry;
 
 #[allow(non_camel_case_types)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
@@ -65,6 +70,81 @@ pub enum Led {
     C_U_d_4,
 }
 
+impl Led {
+    pub const ALL: [Led; 48] = [
+        Led::C_RU_1_4,
+        Led::C_RD_1_4,
+        Led::B_RU_1_4,
+        Led::B_RD_1_4,
+        Led::E_RU_1_4,
+        Led::E_RD_1_4,
+        Led::B_L_a_1,
+        Led::B_U_a_1,
+        Led::C_L_a_1,
+        Led::C_U_a_1,
+        Led::D_L_a_1,
+        Led::D_U_a_1,
+        Led::C_L_1_2,
+        Led::C_U_1_2,
+        Led::D_L_1_2,
+        Led::D_U_1_2,
+        Led::A_L_b_2,
+        Led::A_U_b_2,
+        Led::A_R_b_2,
+        Led::C_L_b_2,
+        Led::C_U_b_2,
+        Led::C_R_b_2,
+        Led::D_L_b_2,
+        Led::D_U_b_2,
+        Led::D_R_b_2,
+        Led::A_R_3_2,
+        Led::A_U_3_2,
+        Led::D_R_3_2,
+        Led::D_U_3_2,
+        Led::A_L_c_3,
+        Led::A_R_c_3,
+        Led::B_L_c_3,
+        Led::B_R_c_3,
+        Led::D_L_c_3,
+        Led::D_R_c_3,
+        Led::A_L_3_4,
+        Led::A_U_3_4,
+        Led::B_L_3_4,
+        Led::B_U_3_4,
+        Led::A_LD_d_4,
+        Led::A_LU_d_4,
+        Led::A_U_d_4,
+        Led::B_LD_d_4,
+        Led::B_LU_d_4,
+        Led::B_U_d_4,
+        Led::C_LD_d_4,
+        Led::C_LU_d_4,
+        Led::C_U_d_4,
+    ];
+}
+
+///the variant name, ie `B_L_a_1`
+impl FromStr for Led {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Led::ALL
+            .iter()
+            .find(|led| format!("{:?}", led) == s)
+            .copied()
+            .ok_or_else(|| format!("unknown led '{}'", s))
+    }
+}
+
+/// what the light controller thread is asked to do
+#[derive(Debug, Clone, PartialEq)]
+pub enum LedCommand {
+    ///lights exactly these leds, the decisions of the route indicators
+    Show(Vec<Led>),
+    ///turns one led on or off, until the next `Show`
+    Set(Led, bool),
+}
+
 pub struct LedAddr(pub u8, pub u8);
 impl LedAddr {
     pub fn i2c(&self) -> u8 {
@@ -140,13 +220,15 @@ pub struct LightController {
     port: SerialPort,
     buffer: [u8; 256],
     state: HashMap<Led, bool>,
+    ///receives the outcome and latency of every write
+    pub telemetry: Option<Arc<Telemetry>>,
 }
 
 impl LightController {
     pub fn create_and_init(port_name: &str, baudrate: u32) -> Result<Self, std::io::Error> {
         let port = SerialPort::open(port_name, baudrate);
         if let Err(e) = port {
-            println!("[LightController] {}", e);
+            error!("Failed to open light controller on {}: {}", port_name, e);
             return Err(e);
         }
         let mut port = port.unwrap();
@@ -154,13 +236,12 @@ impl LightController {
         port.set_write_timeout(Duration::from_millis(300)).unwrap();
         let mut buffer = [0; 256];
         let mut off = 0;
-        println!("====starting initialisation=====");
-        println!("Waiting for start message(\"Listening for input...\")\n");
+        info!("Waiting for the light controller's start message (\"Listening for input...\")");
         while &buffer[..22] != "Listening for input...".as_bytes() {
             if let Ok(read) = port.read(&mut buffer[off..]) {
                 off += read;
-                println!(
-                    "[start message] {}",
+                debug!(
+                    "start message so far: {}",
                     buffer[..off + 1]
                         .iter()
                         .map(|c| *c as char)
@@ -168,12 +249,13 @@ impl LightController {
                 );
             }
         }
-        println!("====successfully initialised serial port!=====\n");
+        info!("Initialised the light controller on {}", port_name);
         buffer = [0; 256];
         let mut controller = Self {
             port,
             buffer,
             state: HashMap::new(),
+            telemetry: None,
         };
         crate::delay(2);
         controller.clear_all();
@@ -181,6 +263,7 @@ impl LightController {
     }
 
     fn clear_all(&mut self) {
+        debug!("Clearing every led");
         for i2c_addr in 0..5 {
             for led in 0..16 {
                 self.set_led_from_addr(i2c_addr, led, 0);
@@ -197,6 +280,7 @@ impl LightController {
     }
 
     pub fn set_led(&mut self, led: Led, state: bool) {
+        debug!("Setting {:?} {}", led, if state { "on" } else { "off" });
         let led_addr = led_to_addr(led);
         let i2c_addr = led_addr.i2c();
         let led_addr = led_addr.led();
@@ -205,8 +289,10 @@ impl LightController {
     }
 
     fn set_led_from_addr(&mut self, i2c_addr: u8, led_addr: u8, state: u8) {
+        let start = Instant::now();
+        let mut ok = false;
         if let Err(e) = write!(&mut self.port, "{}{:02}{}\n", i2c_addr, led_addr, state) {
-            println!("Error setting led: {:?}", e);
+            warn!("Error setting led: {:?}", e);
         } else {
             // self.port.flush().unwrap();
             if let Err(e) = self.port.read(&mut self.buffer) {
@@ -218,8 +304,13 @@ impl LightController {
                 //         .collect::<String>(),
                 // );
                 // } else {
-                println!("**No response after setting led**, {}", e);
+                warn!("No response after setting led: {}", e);
+            } else {
+                ok = true;
             }
         }
+        if let Some(telemetry) = &self.telemetry {
+            telemetry.record_serial_write(start.elapsed(), ok);
+        }
     }
 }
@@ -0,0 +1,201 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{self, ErrorKind};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use log::{info, warn};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use tungstenite::{Message, WebSocket};
+
+use crate::pipeline::Snapshot;
+
+///longest the listener waits between checks for new clients and whether it should stop
+const ACCEPT_POLL: Duration = Duration::from_millis(200);
+
+/// what a client was last sent, keyed so changes can be found
+#[derive(Default)]
+struct StreamState {
+    vehicles: HashMap<String, Value>,
+    lanes: HashMap<String, Value>,
+    ///keyed by indicator road and destination
+    routes: HashMap<String, Value>,
+    leds: HashSet<String>,
+}
+
+impl StreamState {
+    fn from_snapshot(snapshot: &Snapshot) -> Self {
+        // records carry the time of the update, which would mark every one as changed
+        fn keyed<T: Serialize>(
+            records: &[T],
+            key: impl Fn(&Value) -> String,
+        ) -> HashMap<String, Value> {
+            records
+                .iter()
+                .map(|record| {
+                    let mut value = json!(record);
+                    value.as_object_mut().unwrap().remove("time");
+                    (key(&value), value)
+                })
+                .collect()
+        }
+        Self {
+            vehicles: keyed(&snapshot.vehicles, |v| v["id"].to_string()),
+            lanes: keyed(&snapshot.lanes, |v| v["lane"].to_string()),
+            routes: keyed(&snapshot.routes, |v| {
+                format!("{} {}", v["road"], v["destination"])
+            }),
+            leds: snapshot
+                .leds
+                .iter()
+                .map(|led| format!("{:?}", led))
+                .collect(),
+        }
+    }
+
+    fn to_message(&self) -> Value {
+        let mut leds = self.leds.iter().collect::<Vec<_>>();
+        leds.sort();
+        json!({
+            "type": "snapshot",
+            "vehicles": self.vehicles.values().collect::<Vec<_>>(),
+            "lanes": self.lanes.values().collect::<Vec<_>>(),
+            "routes": self.routes.values().collect::<Vec<_>>(),
+            "leds": leds,
+        })
+    }
+
+    ///what changed since `old`, `None` if nothing did
+    fn delta(&self, old: &StreamState) -> Option<Value> {
+        let mut delta = Map::new();
+        for (name, new, old) in [
+            ("vehicles", &self.vehicles, &old.vehicles),
+            ("lanes", &self.lanes, &old.lanes),
+            ("routes", &self.routes, &old.routes),
+        ] {
+            let updated = new
+                .iter()
+                .filter(|(k, v)| old.get(*k) != Some(*v))
+                .map(|(_, v)| v)
+                .collect::<Vec<_>>();
+            let removed = old
+                .keys()
+                .filter(|k| !new.contains_key(*k))
+                .map(|k| id(name, &old[k]))
+                .collect::<Vec<_>>();
+            if !updated.is_empty() || !removed.is_empty() {
+                delta.insert(
+                    name.to_string(),
+                    json!({ "updated": updated, "removed": removed }),
+                );
+            }
+        }
+        let on = self.leds.difference(&old.leds).collect::<Vec<_>>();
+        let off = old.leds.difference(&self.leds).collect::<Vec<_>>();
+        if !on.is_empty() || !off.is_empty() {
+            delta.insert("leds".to_string(), json!({ "on": on, "off": off }));
+        }
+        if delta.is_empty() {
+            return None;
+        }
+        delta.insert("type".to_string(), json!("delta"));
+        Some(Value::Object(delta))
+    }
+}
+
+///what identifies an entry of a section to clients
+fn id(section: &str, entry: &Value) -> Value {
+    match section {
+        "vehicles" => entry["id"].clone(),
+        "lanes" => entry["lane"].clone(),
+        _ => json!({ "road": entry["road"], "destination": entry["destination"] }),
+    }
+}
+
+/// streams the pipeline state to WebSocket clients until `is_running` is cleared
+///
+/// every client is sent a `{"type": "snapshot", ...}` message with all vehicles, lanes, route
+/// decisions and lit leds when it connects, then a `{"type": "delta", ...}` message every
+/// `interval` with only what changed: the `updated` entries of `vehicles`, `lanes` and `routes`
+/// with the ids of the `removed` ones, and the leds turned `on` and `off`
+pub fn spawn(
+    addr: &str,
+    snapshot: Arc<Mutex<Snapshot>>,
+    interval: Duration,
+    is_running: Arc<AtomicBool>,
+) -> io::Result<JoinHandle<()>> {
+    let listener = TcpListener::bind(addr)?;
+    listener.set_nonblocking(true)?;
+    info!("Streaming live state on ws://{}", addr);
+    Ok(thread::spawn(move || {
+        let mut clients: Vec<JoinHandle<()>> = vec![];
+        while is_running.load(Ordering::Relaxed) {
+            match listener.accept() {
+                Ok((stream, peer)) => {
+                    let snapshot = Arc::clone(&snapshot);
+                    let is_running = Arc::clone(&is_running);
+                    clients.push(thread::spawn(move || {
+                        if let Err(e) = serve(stream, &snapshot, interval, &is_running) {
+                            info!("Live stream to {} ended: {}", peer, e);
+                        }
+                    }));
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(ACCEPT_POLL),
+                Err(e) => warn!("Live stream failed to accept a client: {}", e),
+            }
+            clients.retain(|client| !client.is_finished());
+        }
+        for client in clients {
+            let _ = client.join();
+        }
+    }))
+}
+
+fn serve(
+    stream: TcpStream,
+    snapshot: &Mutex<Snapshot>,
+    interval: Duration,
+    is_running: &AtomicBool,
+) -> Result<(), String> {
+    stream.set_nonblocking(false).map_err(|e| e.to_string())?;
+    let mut socket = tungstenite::accept(stream).map_err(|e| e.to_string())?;
+    // only read to notice the client closing, so reads must not hold up the updates
+    socket
+        .get_mut()
+        .set_nonblocking(true)
+        .map_err(|e| e.to_string())?;
+
+    let mut sent = StreamState::from_snapshot(&snapshot.lock().unwrap());
+    send(&mut socket, sent.to_message())?;
+    while is_running.load(Ordering::Relaxed) {
+        thread::sleep(interval);
+        match socket.read() {
+            Ok(Message::Close(_)) => return Ok(()),
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(e)) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+            Err(e) => return Err(e.to_string()),
+        }
+
+        let state = StreamState::from_snapshot(&snapshot.lock().unwrap());
+        if let Some(delta) = state.delta(&sent) {
+            send(&mut socket, delta)?;
+            sent = state;
+        }
+    }
+    let _ = socket.close(None);
+    let _ = socket.flush();
+    Ok(())
+}
+
+fn send(socket: &mut WebSocket<TcpStream>, message: Value) -> Result<(), String> {
+    match socket.send(Message::text(message.to_string())) {
+        Ok(()) => Ok(()),
+        // queued, written out with the next message
+        Err(tungstenite::Error::Io(e)) if e.kind() == ErrorKind::WouldBlock => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
@@ -0,0 +1,157 @@
+use std::cmp::Reverse;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use log::{LevelFilter, Log, Metadata, Record};
+use serde_json::json;
+
+use crate::config::Config;
+use crate::metrics_store::{self, format_time};
+
+///module paths of the rerouter start with this, left out of console lines and log filters
+const CRATE_TARGET: &str = "rerouter";
+
+/// the level logged up to for each module, ie `info light_controller=debug rumqttc=warn`
+///
+/// modules of the rerouter are named without the crate, other crates by their name
+#[derive(Debug, Clone)]
+pub struct LogFilter {
+    default: LevelFilter,
+    ///most specific module first
+    modules: Vec<(String, LevelFilter)>,
+}
+
+impl LogFilter {
+    fn level(&self, target: &str) -> LevelFilter {
+        let module = short_target(target);
+        self.modules
+            .iter()
+            .find(|(m, _)| {
+                module
+                    .strip_prefix(m.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default)
+    }
+
+    fn max(&self) -> LevelFilter {
+        self.modules
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, Ord::max)
+    }
+}
+
+impl FromStr for LogFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let level = |l: &str| {
+            l.parse::<LevelFilter>()
+                .map_err(|_| format!("unknown log level '{}'", l))
+        };
+        let mut filter = LogFilter {
+            default: LevelFilter::Info,
+            modules: vec![],
+        };
+        for part in s.split_whitespace() {
+            match part.split_once('=') {
+                Some((module, l)) => filter.modules.push((module.to_string(), level(l)?)),
+                None => filter.default = level(part)?,
+            }
+        }
+        filter.modules.sort_by_key(|(m, _)| Reverse(m.len()));
+        Ok(filter)
+    }
+}
+
+///`main` for the crate root, the module path for the rest of the rerouter, other targets as is
+fn short_target(target: &str) -> &str {
+    match target.strip_prefix(CRATE_TARGET) {
+        Some("") => "main",
+        Some(module) => module.strip_prefix("::").unwrap_or(target),
+        None => target,
+    }
+}
+
+/// logs timestamped lines to stderr, and json lines to a file if there is one
+struct Logger {
+    console: LogFilter,
+    file: Option<(LogFilter, Mutex<File>)>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let target = metadata.target();
+        metadata.level() <= self.console.level(target)
+            || self
+                .file
+                .as_ref()
+                .is_some_and(|(filter, _)| metadata.level() <= filter.level(target))
+    }
+
+    fn log(&self, record: &Record) {
+        let (level, target) = (record.level(), record.target());
+        let time = format_time(metrics_store::unix_time(SystemTime::now()));
+        if level <= self.console.level(target) {
+            eprintln!(
+                "{} {:<5} {}: {}",
+                time,
+                level,
+                short_target(target),
+                record.args()
+            );
+        }
+        if let Some((filter, file)) = &self.file {
+            if level <= filter.level(target) {
+                let line = json!({
+                    "time": time,
+                    "level": level.as_str(),
+                    "target": target,
+                    "message": record.args().to_string(),
+                });
+                let _ = writeln!(file.lock().unwrap(), "{}", line);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some((_, file)) = &self.file {
+            let _ = file.lock().unwrap().flush();
+        }
+    }
+}
+
+///logs to the console up to `log_level` and, with a `log_file`, appends json lines to it up to
+///`log_file_level`
+///
+///`config` is expected to have been validated
+pub fn init(config: &Config) -> Result<(), String> {
+    let console = config.log_level.parse::<LogFilter>()?;
+    let file = match &config.log_file {
+        Some(path) => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| format!("Failed to open log file {}: {}", path.display(), e))?;
+            Some((
+                config.log_file_level.parse::<LogFilter>()?,
+                Mutex::new(file),
+            ))
+        }
+        None => None,
+    };
+    let max = file
+        .as_ref()
+        .map(|(filter, _)| filter.max())
+        .unwrap_or(LevelFilter::Off)
+        .max(console.max());
+    log::set_boxed_logger(Box::new(Logger { console, file })).map_err(|e| e.to_string())?;
+    log::set_max_level(max);
+    Ok(())
+}
@@ -1,29 +1,57 @@
+mod api;
+mod calibration;
+mod config;
+mod console;
+mod cost_smoothing;
+#[cfg(feature = "dashboard")]
+mod dashboard;
+mod forecast;
+mod incident;
+mod kalman;
+mod lane_geometry;
 mod light_controller;
+mod live_stream;
+mod logger;
 mod map;
 mod mask_loader;
+mod metrics_store;
+mod mqtt;
+mod od_matrix;
+mod pipeline;
 mod presets;
+mod queue;
 mod route_indicator;
+mod schedule;
+mod telemetry;
+mod travel_time;
+mod turning_movements;
+mod vehicle_class;
 mod vehicle_tracker;
 
-use sdl2::event::Event;
-use sdl2::image::LoadTexture;
-use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
-use sdl2::rect::Rect;
-use sdl2::render::{RenderTarget, TextureQuery};
-use sdl2::sys::{KeyCode, Window};
-
+use calibration::Calibration;
+use config::Config;
 use map::{IntersectionId, LaneId, RoadId, RoadMap};
 
-use light_controller::{Led, LightController};
-use vehicle_tracker::Tracker;
+use light_controller::{Led, LedCommand, LightController};
+use metrics_store::MetricsStore;
+use pipeline::{Pipeline, METRICS_INTERVAL};
+use telemetry::Telemetry;
+use vehicle_class::VehicleClass;
 
+use log::{error, info, warn};
 use std::io::Error;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
-use std::{io::BufRead, io::BufReader, num::ParseFloatError, process};
+use std::{io::BufRead, io::BufReader, process};
+
+///longest the light controller waits for new route decisions before checking it should stop
+const CONTROLLER_POLL: Duration = Duration::from_millis(200);
+///wait before starting the vehicle detector again after it exits
+const DETECTOR_RESTART_DELAY: Duration = Duration::from_secs(1);
 
 #[derive(Debug)]
 pub struct RxData {
@@ -31,6 +59,37 @@ pub struct RxData {
     x: f64,
     y: f64,
     vel: f64,
+    class: Option<VehicleClass>,
+    ///width and height of the detection in pixels
+    size: Option<(f64, f64)>,
+}
+
+impl RxData {
+    ///parses a detection record, `id,x,y,vel[,class[,w,h]]`
+    ///
+    ///an empty or unknown class (ie `-`) leaves the class to be guessed from the size
+    fn parse(s: &str) -> Option<Self> {
+        let fields = s.split(',').collect::<Vec<&str>>();
+        if ![4, 5, 7].contains(&fields.len()) {
+            return None;
+        }
+        let numbers = fields[..4]
+            .iter()
+            .map(|w| w.parse::<f64>().ok())
+            .collect::<Option<Vec<f64>>>()?;
+        let size = match fields.get(5..7) {
+            Some([w, h]) => Some((w.parse::<f64>().ok()?, h.parse::<f64>().ok()?)),
+            _ => None,
+        };
+        Some(RxData {
+            id: numbers[0] as u64,
+            x: numbers[1],
+            y: numbers[2],
+            vel: numbers[3],
+            class: fields.get(4).and_then(|c| c.parse().ok()),
+            size,
+        })
+    }
 }
 
 #[derive(Debug)]
@@ -40,346 +99,429 @@ fn main() {
     // load_road_masks();
     // controller.set_led(Led::E_RD_1_4, true);
 
+    let args = std::env::args().collect::<Vec<String>>();
+    match args.get(1).map(|s| s.as_str()) {
+        Some("calibrate") => {
+            let (args, settings) = Config::split_settings(&args[2..]);
+            calibrate(&args, &load_config(&settings));
+            return;
+        }
+        Some("query") => {
+            let (args, settings) = Config::split_settings(&args[2..]);
+            query(&args, &load_config(&settings));
+            return;
+        }
+        _ => {}
+    }
+
+    let is_help = |a: &String| a == "--help" || a == "-h";
+    if args.iter().any(is_help) {
+        let args = args[1..].iter().filter(|a| !is_help(a)).cloned();
+        print!("{}", load_config(&args.collect::<Vec<_>>()).help());
+        return;
+    }
+
+    let mut config = load_config(&args[1..]);
+    config.headless |= !cfg!(feature = "dashboard");
+    if let Err(e) = logger::init(&config) {
+        println!("{}", e);
+        process::exit(1)
+    }
+    let detector_path = config.check_run_files().unwrap_or_else(|e| {
+        println!("{}\nsee rerouter --help", e);
+        process::exit(1)
+    });
+
     let is_running = Arc::new(AtomicBool::new(true));
 
     let is_running_detector = Arc::clone(&is_running);
     let is_running_controller = Arc::clone(&is_running);
+    let is_running_signal = Arc::clone(&is_running);
+
+    // a second signal stops straight away in case the shutdown itself hangs
+    ctrlc::set_handler(move || {
+        if is_running_signal.swap(false, Ordering::Relaxed) {
+            info!("Shutting down");
+        } else {
+            process::exit(130);
+        }
+    })
+    .expect("Failed to set signal handler");
 
+    let telemetry = Arc::new(Telemetry::default());
     let (tx, rx) = std::sync::mpsc::channel();
 
+    let python = config.python.clone();
+    let detector =
+        spawn_detector(&python, &detector_path).expect("Unable to spawn vehicle detector");
+    let detector = Arc::new(Mutex::new(detector));
+    let detector_child = Arc::clone(&detector);
+    let detector_telemetry = Arc::clone(&telemetry);
+
+    // restarts the detector whenever it exits, ends once it is killed at shutdown
     let detector_thread = std::thread::spawn(move || {
         let is_running = is_running_detector;
-        let args = std::env::args().collect::<Vec<String>>();
-        let detector_path = args.get(1).unwrap().clone();
-        let mut detector = process::Command::new(format!("python3"))
-            .arg(detector_path)
-            .stdout(process::Stdio::piped())
-            .spawn()
-            .expect("Unable to spawn vehicle detector");
-
-        let stdout = detector.stdout.as_mut().unwrap();
-        let stdout_reader = BufReader::new(stdout);
-        let stdout_lines = stdout_reader.lines();
-
-        for line in stdout_lines {
-            if !is_running.load(Ordering::Relaxed) {
-                return;
-            }
-            if line.is_err() {
-                continue;
-            }
+        let telemetry = detector_telemetry;
+        loop {
+            let stdout = detector_child.lock().unwrap().stdout.take().unwrap();
+            let stdout_reader = BufReader::new(stdout);
+            let stdout_lines = stdout_reader.lines();
 
-            let s = line.unwrap();
-            let s = s.trim();
-            let mut datas = vec![];
-            'a: for s in s.split_whitespace() {
-                let parts = s
-                    .split(",")
-                    .map(|w| w.parse::<f64>())
-                    .collect::<Vec<Result<f64, ParseFloatError>>>();
-                if parts.len() != 4 {
+            for line in stdout_lines {
+                if !is_running.load(Ordering::Relaxed) {
+                    return;
+                }
+                if line.is_err() {
                     continue;
                 }
 
-                for x in &parts {
-                    if let Err(_) = x {
-                        continue 'a;
+                let s = line.unwrap();
+                let s = s.trim();
+                let mut datas = vec![];
+                for s in s.split_whitespace() {
+                    if let Some(rx_data) = RxData::parse(s) {
+                        datas.push(rx_data);
+                    } else {
+                        telemetry.parse_errors.fetch_add(1, Ordering::Relaxed);
                     }
                 }
+                telemetry
+                    .detections
+                    .fetch_add(datas.len() as u64, Ordering::Relaxed);
+                let _ = tx.send(RxDatas(datas));
+            }
 
-                let rx_data = RxData {
-                    id: *parts[0].as_ref().unwrap() as u64,
-                    x: *parts[1].as_ref().unwrap(),
-                    y: *parts[2].as_ref().unwrap(),
-                    vel: *parts[3].as_ref().unwrap(),
-                };
-                datas.push(rx_data);
+            if !is_running.load(Ordering::Relaxed) {
+                return;
+            }
+            let mut child = detector_child.lock().unwrap();
+            // stdout may have closed with the detector still running
+            let _ = child.kill();
+            match child.wait() {
+                Ok(status) => warn!("Vehicle detector exited ({})", status),
+                Err(e) => warn!("Vehicle detector exited: {}", e),
+            }
+            drop(child);
+            thread::sleep(DETECTOR_RESTART_DELAY);
+
+            // checked with the detector locked, so a detector started here is killed at shutdown
+            let mut child = detector_child.lock().unwrap();
+            if !is_running.load(Ordering::Relaxed) {
+                return;
+            }
+            match spawn_detector(&python, &detector_path) {
+                Ok(restarted) => {
+                    info!("Restarted vehicle detector");
+                    *child = restarted;
+                    telemetry.detector_restarts.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(e) => {
+                    error!("Couldn't restart vehicle detector: {}", e);
+                    return;
+                }
             }
-            let _ = tx.send(RxDatas(datas));
         }
     });
 
-    let map = presets::create_map();
+    let mut map = presets::create_map();
+    map.use_forecast = config.forecast_routing;
     let map = Arc::new(std::sync::Mutex::new(map));
 
-    let map_clone = Arc::clone(&map);
+    let (controller_tx, controller_rx) = std::sync::mpsc::channel::<LedCommand>();
+    let (port, baud_rate) = (config.serial_port.clone(), config.baud_rate);
+    let safe_leds = config.safe_leds.clone();
+    let controller_telemetry = Arc::clone(&telemetry);
     let controller_thread = std::thread::spawn(move || {
         let is_running = is_running_controller;
-        let port = &std::env::var("PORT").expect("Serial port name");
-        let mut controller = LightController::create_and_init(port, 115200);
+        let mut controller = LightController::create_and_init(&port, baud_rate);
 
         while controller.is_err() {
             if !is_running.load(Ordering::Relaxed) {
                 return;
             }
-            println!("Initialising light controller failed");
-            println!("Retrying in 400ms");
+            warn!("Initialising light controller failed, retrying in 400ms");
             thread::sleep(Duration::from_millis(400));
-            controller = LightController::create_and_init(port, 115200);
+            controller = LightController::create_and_init(&port, baud_rate);
         }
 
         let mut controller = controller.unwrap();
-
-        let map = map_clone;
-        let route_indicators = presets::create_route_indicators();
-        let mut last_update = Instant::now();
-
-        let mut led_state_buffer = vec![];
-        let mut update = || {
-            {
-                let map = map.lock().unwrap();
-                for indicator in &route_indicators {
-                    let curr_road = indicator.road_id;
-                    let n1 = indicator.int_id;
-                    for (n2, leds) in &indicator.routes {
-                        let (_, maybe_road) = map.best_direction(n1, *n2, Some(&curr_road));
-                        if let Some(road) = maybe_road {
-                            if let Some(led) = leds.get(&road) {
-                                led_state_buffer.push(led);
-                            }
+        controller.telemetry = Some(controller_telemetry);
+
+        // the pipeline sends the leds to light whenever the route decisions change
+        while is_running.load(Ordering::Relaxed) {
+            let mut commands = match controller_rx.recv_timeout(CONTROLLER_POLL) {
+                Ok(command) => vec![command],
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            commands.extend(controller_rx.try_iter());
+            // only the latest decisions matter if several queued up while the serial port was busy
+            let latest = commands
+                .iter()
+                .rposition(|c| matches!(c, LedCommand::Show(_)))
+                .unwrap_or(0);
+
+            for command in &commands[latest..] {
+                match command {
+                    LedCommand::Show(leds) => {
+                        controller.clear();
+                        delay(90);
+
+                        for led in leds {
+                            controller.set_led(*led, true)
                         }
                     }
+                    LedCommand::Set(led, on) => controller.set_led(*led, *on),
                 }
             }
+        }
 
-            controller.clear();
-            delay(90);
+        controller.clear();
+        for led in &safe_leds {
+            controller.set_led(*led, true)
+        }
+    });
 
-            for led in &led_state_buffer {
-                controller.set_led(**led, true)
+    let leds_tx = controller_tx.clone();
+    let mut pipeline = Pipeline::new(
+        Arc::clone(&map),
+        controller_tx,
+        Arc::clone(&telemetry),
+        &config,
+    );
+
+    let stream_thread = if config.stream_addr.is_empty() {
+        None
+    } else {
+        match live_stream::spawn(
+            &config.stream_addr,
+            pipeline.snapshot(),
+            config.stream_interval,
+            Arc::clone(&is_running),
+        ) {
+            Ok(thread) => Some(thread),
+            Err(e) => {
+                error!(
+                    "Couldn't start the live stream on {}: {}",
+                    config.stream_addr, e
+                );
+                None
             }
-            led_state_buffer.clear();
+        }
+    };
+
+    let api_thread = if config.api_addr.is_empty() {
+        None
+    } else {
+        let state = api::ApiState {
+            map: Arc::clone(&map),
+            snapshot: pipeline.snapshot(),
+            control: pipeline.control(),
+            telemetry: Arc::clone(&telemetry),
         };
-
-        update();
-
-        loop {
-            if !is_running.load(Ordering::Relaxed) {
-                return;
+        match api::spawn(&config.api_addr, state, Arc::clone(&is_running)) {
+            Ok(thread) => Some(thread),
+            Err(e) => {
+                error!("Couldn't start the HTTP API on {}: {}", config.api_addr, e);
+                None
             }
-            if last_update.elapsed().as_millis() < 300 {
-                continue;
-            }
-            update();
-            last_update = Instant::now();
         }
-    });
+    };
+
+    let mqtt_thread = if config.mqtt_broker.is_empty() {
+        None
+    } else {
+        Some(mqtt::spawn(
+            &config,
+            Arc::clone(&map),
+            pipeline.snapshot(),
+            pipeline.control(),
+            Arc::clone(&is_running),
+        ))
+    };
+
+    if config.console {
+        let state = console::ConsoleState {
+            map: Arc::clone(&map),
+            snapshot: pipeline.snapshot(),
+            control: pipeline.control(),
+            leds: leds_tx,
+        };
+        console::spawn(state, Arc::clone(&is_running));
+    }
 
-    let map = Arc::clone(&map);
-
-    let mut tracker = Tracker::new();
-
-    let ctx = sdl2::init().unwrap();
-    let video_subsys = ctx.video().unwrap();
-    video_subsys.gl_attr().set_multisample_buffers(1);
-    video_subsys.gl_attr().set_multisample_samples(2);
-    video_subsys.gl_attr().set_accelerated_visual(true);
-    // println!("{}", video_subsys.gl_attr().context_major_version());
-
-    let window = video_subsys
-        .window("Automatic traffic router (traffic map)", 840, 480)
-        .position_centered()
-        .build()
-        .unwrap();
-    let ttf_context = sdl2::ttf::init()
-        .map_err(|e| e.to_string())
-        .expect("ttf context ( some font thing )");
-
-    let mut canvas = window.into_canvas().build().unwrap();
-    let font = ttf_context
-        .load_font("../fonts/FiraCode-Medium.ttf", 14)
-        .expect("font");
-    let texture_creator = canvas.texture_creator();
-    let texture = texture_creator.load_texture("../map-outline.png").unwrap();
-
-    let mut vel_coeff = 0.0;
-    let mut density_coeff = 500.0;
-
-    let mut vel_rect;
-    let mut density_rect;
-
-    let mut event_pump = ctx.event_pump().unwrap();
-    'running: loop {
-        canvas.set_draw_color(Color::RGBA(20, 20, 20, 200));
-        canvas.clear();
-        canvas
-            .copy(&texture, None, Some(Rect::new(0, 0, 640, 480)))
-            .expect("rendered map outline");
-        canvas.set_draw_color(Color::MAGENTA);
-        if let Ok(d) = rx.recv() {
-            for d in d.0 {
-                // canvas.fill_rect(Rect::new(d.x as i32, d.y as i32, 8, 8));
-                tracker.on_recv(d)
-            }
-        }
+    if config.headless {
+        run_headless(&mut pipeline, &rx, &is_running);
+    } else {
+        #[cfg(feature = "dashboard")]
+        dashboard::run(&mut pipeline, &rx, &is_running, &config);
+    }
 
-        canvas.set_draw_color(Color::CYAN);
-        for (_, vehicle) in &tracker.vehicles {
-            let _ = canvas.fill_rect(Rect::new(
-                vehicle.pos.0 as i32 - 5,
-                vehicle.pos.1 as i32 - 5,
-                10,
-                10,
-            ));
-
-            let lane = vehicle.lane_id;
-            let n1_id = lane.0 .0;
-            let n2_id = lane.0 .1;
-            let lane_id = lane.1;
-
-            let srf = font
-                .render(&format!(
-                    "{}-{} {}",
-                    if n1_id < 10 {
-                        format!("{}", n1_id)
-                    } else {
-                        format!("{}", n1_id as u8 as char)
-                    },
-                    if n2_id < 10 {
-                        format!("{}", n2_id)
-                    } else {
-                        format!("{}", n2_id as u8 as char)
-                    },
-                    if lane_id == 1 { "right" } else { "left" },
-                ))
-                .blended(Color::MAGENTA)
-                .expect("rendered text");
-            let texture = texture_creator
-                .create_texture_from_surface(srf)
-                .expect("texture");
-            let TextureQuery { width, height, .. } = texture.query();
-            let _ = canvas.copy(
-                &texture,
-                None,
-                Some(Rect::new(
-                    vehicle.pos.0 as i32 + 5,
-                    vehicle.pos.1 as i32 + 5,
-                    width,
-                    height,
-                )),
-            );
-        }
+    is_running.store(false, Ordering::Relaxed);
+    pipeline.finish();
+    // closes the route decision channel, so the controller sets the safe leds straight away
+    drop(pipeline);
 
-        {
-            let mut map = map.lock().unwrap();
-            let mut y = 200;
-            let mut visited = vec![];
-            for (LaneId(road_id, lane_id), _lane) in &tracker.lanes {
-                let road_len = map.road_length(road_id).unwrap();
-                let cost = tracker
-                    .lane_dynamic_cost(
-                        &LaneId(*road_id, *lane_id),
-                        road_len,
-                        density_coeff,
-                        vel_coeff,
-                        0.0,
-                    )
-                    .unwrap();
-
-                if *lane_id == 0 {
-                    map.set_cost(road_id, Some(cost), None);
-                } else {
-                    map.set_cost(road_id, None, Some(cost));
-                }
+    let mut detector = detector.lock().unwrap();
+    if let Err(e) = detector.kill() {
+        warn!("Couldn't stop vehicle detector: {}", e);
+    }
+    let _ = detector.wait();
+    drop(detector);
+
+    let deadline = Instant::now() + config.shutdown_timeout;
+    join_until(controller_thread, "controller", deadline);
+    join_until(detector_thread, "detector", deadline);
+    if let Some(api_thread) = api_thread {
+        join_until(api_thread, "HTTP API", deadline);
+    }
+    if let Some(stream_thread) = stream_thread {
+        join_until(stream_thread, "live stream", deadline);
+    }
+    if let Some(mqtt_thread) = mqtt_thread {
+        join_until(mqtt_thread, "MQTT", deadline);
+    }
+}
 
-                if visited.contains(road_id) {
-                    continue;
-                }
+fn spawn_detector(python: &str, detector: &Path) -> std::io::Result<process::Child> {
+    process::Command::new(python)
+        .arg(detector)
+        .stdout(process::Stdio::piped())
+        .spawn()
+}
 
-                let road = map.roads.get(road_id).unwrap();
-                let srf = font
-                    .render(&format!(
-                        "road {}-{} cost: {}, {}",
-                        road_id.0,
-                        road_id.1,
-                        road.cost_from(&IntersectionId(road_id.0), true).round(),
-                        road.cost_from(&IntersectionId(road_id.1), true).round()
-                    ))
-                    .blended(Color::CYAN)
-                    .expect("rendered text");
-                let texture = texture_creator
-                    .create_texture_from_surface(srf)
-                    .expect("texture");
-                let TextureQuery { width, height, .. } = texture.query();
-                let text_rect = Rect::new(820 - width as i32, y, width, height);
-                let _ = canvas.copy(&texture, None, Some(text_rect));
-                y += height as i32 + 5;
-                visited.push(*road_id);
-            }
+///joins the thread unless it is still running at `deadline`, then it is left behind
+fn join_until(thread: thread::JoinHandle<()>, name: &str, deadline: Instant) {
+    while !thread.is_finished() {
+        if Instant::now() >= deadline {
+            warn!("The {} thread didn't stop in time", name);
+            return;
         }
+        delay(10);
+    }
+    if let Err(e) = thread.join() {
+        error!("Couldn't join {} thread: {:?}", name, e);
+    }
+}
 
-        canvas.set_draw_color(Color::WHITE);
-
-        let srf = font
-            .render(&format!("Speed coeff: {}", vel_coeff))
-            .blended(Color::CYAN)
-            .expect("rendered text");
-        let texture = texture_creator
-            .create_texture_from_surface(srf)
-            .expect("texture");
-        let TextureQuery { width, height, .. } = texture.query();
-        let text_rect = Rect::new(820 - width as i32, 50, width, height);
-        vel_rect = Rect::new(
-            text_rect.x - 5,
-            text_rect.y - 5,
-            text_rect.width() + 10,
-            text_rect.height() + 10,
-        );
-        let _ = canvas.copy(&texture, None, Some(text_rect));
-        let _ = canvas.draw_rect(vel_rect);
-
-        let srf = font
-            .render(&format!("Density coeff: {}", density_coeff))
-            .blended(Color::CYAN)
-            .expect("rendered text");
-        let texture = texture_creator
-            .create_texture_from_surface(srf)
-            .expect("texture");
-        let TextureQuery { width, height, .. } = texture.query();
-        let text_rect = Rect::new(820 - width as i32, 100, width, height);
-        density_rect = Rect::new(
-            text_rect.x - 5,
-            text_rect.y - 5,
-            text_rect.width() + 10,
-            text_rect.height() + 10,
-        );
-        let _ = canvas.copy(&texture, None, Some(text_rect));
-        let _ = canvas.draw_rect(density_rect);
-
-        let mouse_state = event_pump.mouse_state();
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
-                    ..
-                } => {
-                    is_running.store(false, Ordering::Relaxed);
-                    break 'running;
-                }
+///the config from the default config file and `args`, exits if it is invalid
+fn load_config(args: &[String]) -> Config {
+    Config::from_args(args).unwrap_or_else(|e| {
+        println!("Invalid configuration: {}\nsee rerouter --help", e);
+        process::exit(1)
+    })
+}
 
-                Event::MouseWheel { y, .. } => {
-                    if vel_rect.contains_point((mouse_state.x(), mouse_state.y())) {
-                        vel_coeff += (y.signum() * y * y) as f64;
-                    }
-                    if density_rect.contains_point((mouse_state.x(), mouse_state.y())) {
-                        density_coeff += (y.signum() * y * y) as f64;
-                    }
-                }
-                _ => {}
+///runs the pipeline without a window until the detector stops, sleeping until either
+///detections come in or the next update is due
+fn run_headless(pipeline: &mut Pipeline, rx: &Receiver<RxDatas>, is_running: &AtomicBool) {
+    info!("Running headless");
+    while is_running.load(Ordering::Relaxed) {
+        let wait = pipeline
+            .next_due()
+            .saturating_duration_since(Instant::now());
+        match rx.recv_timeout(wait) {
+            Ok(d) => pipeline.ingest(d),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                warn!("Vehicle detector stopped");
+                is_running.store(false, Ordering::Relaxed);
+                return;
             }
         }
-
-        canvas.present();
-        tracker.update();
+        pipeline.drain(rx);
+        pipeline.run_due(Instant::now());
     }
+}
 
-    if let Err(e) = controller_thread.join() {
-        println!("Couldn't join controller thread: {:?}", e);
-    }
+///`rerouter calibrate <points file> [output file] [camera] [--setting value...]`
+///fits a homography to the `point <px> <py> <wx> <wy>` lines of the points file and saves it,
+///by default to the configured calibration file
+fn calibrate(args: &[String], config: &Config) {
+    let points_path = args
+        .first()
+        .expect("usage: rerouter calibrate <points file> [output file] [camera]");
+    let out_path = args
+        .get(1)
+        .map(PathBuf::from)
+        .unwrap_or(config.calibration.clone());
+
+    let calibration = Calibration::load(points_path).expect("Failed to fit calibration");
+    let calibration = match args.get(2) {
+        Some(camera) => Calibration {
+            camera: camera.clone(),
+            ..calibration
+        },
+        None => calibration,
+    };
+    println!("homography: {:?}", calibration.homography.0);
+    println!(
+        "max reprojection error: {:.3}m over {} points",
+        calibration.max_reprojection_error(),
+        calibration.points.len()
+    );
+    calibration
+        .save(&out_path)
+        .expect("Failed to save calibration");
+    println!("Saved calibration to {}", out_path.display());
+}
 
-    if let Err(e) = detector_thread.join() {
-        println!("Couldn't join detector thread: {:?}", e);
+///`rerouter query <lanes|routes> <from> <to> [lane|road] [--setting value...]`
+///prints the stored lane metrics or route decisions between two times as csv, times are unix
+///seconds, `YYYY-MM-DD` or `YYYY-MM-DDTHH:MM[:SS]` in UTC, a date as `to` covers the whole day
+fn query(args: &[String], config: &Config) {
+    let usage = "usage: rerouter query <lanes|routes> <from> <to> [lane|road]";
+    if args.len() < 3 {
+        println!("{}", usage);
+        return;
+    }
+    let (from, to) = match (
+        metrics_store::parse_time(&args[1]),
+        metrics_store::parse_end_time(&args[2]),
+    ) {
+        (Some(from), Some(to)) => (from, to),
+        _ => {
+            println!(
+                "Invalid time range '{}' to '{}'\n{}",
+                args[1], args[2], usage
+            );
+            return;
+        }
+    };
+    let store = MetricsStore::new(&config.metrics_dir, METRICS_INTERVAL);
+    let result = match args[0].as_str() {
+        "lanes" => args
+            .get(3)
+            .map(|l| l.parse::<LaneId>())
+            .transpose()
+            .and_then(|lane| store.query_lanes(from, to, lane).map_err(|e| e.to_string()))
+            .map(|records| {
+                let lines = records.iter().map(|r| r.to_csv());
+                (metrics_store::LANE_HEADER, lines.collect::<Vec<_>>())
+            }),
+        "routes" => args
+            .get(3)
+            .map(|r| r.parse::<RoadId>())
+            .transpose()
+            .and_then(|road| {
+                store
+                    .query_routes(from, to, road)
+                    .map_err(|e| e.to_string())
+            })
+            .map(|records| {
+                let lines = records.iter().map(|r| r.to_csv());
+                (metrics_store::ROUTE_HEADER, lines.collect::<Vec<_>>())
+            }),
+        _ => Err(usage.to_string()),
+    };
+    match result {
+        Ok((header, lines)) => {
+            println!("{}", header);
+            for line in lines {
+                println!("{}", line);
+            }
+        }
+        Err(e) => println!("Query failed: {}", e),
     }
 }
 
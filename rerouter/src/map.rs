@@ -1,9 +1,17 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
 
-#[derive(Hash, Eq, PartialEq, Clone, Copy, Debug)]
+use log::warn;
+use serde::{Serialize, Serializer};
+use traffic_cost::FREE_FLOW_SPEED;
+
+use crate::forecast::CostForecast;
+
+#[derive(Hash, Eq, PartialEq, PartialOrd, Ord, Clone, Copy, Debug)]
 pub struct RoadId(pub u32, pub u32);
 
-#[derive(Hash, Eq, PartialEq, Clone, Copy, Debug)]
+#[derive(Hash, Eq, PartialEq, PartialOrd, Ord, Clone, Copy, Debug)]
 pub struct LaneId(pub RoadId, pub u32);
 
 #[derive(Hash, Eq, PartialEq, Clone, Copy, Debug)]
@@ -12,6 +20,101 @@ pub struct IntersectionId(pub u32);
 pub struct TravelCostStatic(pub f64, pub f64);
 pub struct TravelCostDynamic(pub f64, pub f64);
 
+///ids below 10 are junctions, the rest are boundary intersections named by a letter
+impl fmt::Display for IntersectionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0 < 10 {
+            write!(f, "{}", self.0)
+        } else {
+            write!(f, "{}", char::from_u32(self.0).unwrap_or('?'))
+        }
+    }
+}
+
+impl fmt::Display for RoadId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", IntersectionId(self.0), IntersectionId(self.1))
+    }
+}
+
+impl fmt::Display for LaneId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.0, if self.1 == 0 { "l" } else { "r" })
+    }
+}
+
+///ids are serialized in their `Display` form, ie `"1-4-l"`
+macro_rules! serialize_as_string {
+    ($($t:ty),*) => {
+        $(impl Serialize for $t {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        })*
+    };
+}
+
+serialize_as_string!(IntersectionId, RoadId, LaneId);
+
+///the inverse of `Display`, a junction number or a boundary letter
+impl FromStr for IntersectionId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(n) = s.parse::<u32>() {
+            return Ok(IntersectionId(n));
+        }
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) if c.is_ascii_alphabetic() => Ok(IntersectionId(c as u32)),
+            _ => Err(format!("invalid intersection '{}'", s)),
+        }
+    }
+}
+
+///written as `<intersection>-<intersection>`, ie `1-4` or `a-1`
+impl FromStr for RoadId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (n1, n2) = s
+            .split_once('-')
+            .ok_or_else(|| format!("invalid road '{}', expected ie '1-4'", s))?;
+        Ok(RoadId(
+            n1.parse::<IntersectionId>()?.0,
+            n2.parse::<IntersectionId>()?.0,
+        ))
+    }
+}
+
+///written as `<road>-l` or `<road>-r` like the road mask names, ie `1-4-l`
+impl FromStr for LaneId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (road, lane) = s
+            .rsplit_once('-')
+            .ok_or_else(|| format!("invalid lane '{}', expected ie '1-4-l'", s))?;
+        let lane = match lane {
+            "l" => 0,
+            "r" => 1,
+            _ => return Err(format!("invalid lane '{}', expected 'l' or 'r'", lane)),
+        };
+        Ok(LaneId(road.parse()?, lane))
+    }
+}
+
+///lane 0 runs from `RoadId.0` to `RoadId.1`, lane 1 the other way
+impl LaneId {
+    pub fn entry_id(&self) -> IntersectionId {
+        IntersectionId(if self.1 == 0 { self.0 .0 } else { self.0 .1 })
+    }
+
+    pub fn exit_id(&self) -> IntersectionId {
+        IntersectionId(if self.1 == 0 { self.0 .1 } else { self.0 .0 })
+    }
+}
+
 impl RoadId {
     pub fn get_other_id(&self, id: IntersectionId) -> IntersectionId {
         IntersectionId(if self.0 == id.0 { self.1 } else { self.0 })
@@ -27,6 +130,18 @@ pub struct Intersection {
 }
 
 impl Intersection {
+    pub fn id(&self) -> IntersectionId {
+        self.id
+    }
+
+    pub fn pos(&self) -> (u32, u32) {
+        self.pos
+    }
+
+    pub fn roads(&self) -> &[RoadId] {
+        &self.roads
+    }
+
     pub fn new(id: IntersectionId, pos: (u32, u32)) -> Self {
         Self {
             pos,
@@ -51,6 +166,8 @@ pub struct Road {
     length: f64,
     cost_static: TravelCostStatic,
     cost_dynamic: TravelCostDynamic,
+    ///forecast dynamic costs of the forward and backward lanes
+    forecast: (Option<CostForecast>, Option<CostForecast>),
 
     p1: (u32, u32),
     p2: (u32, u32),
@@ -72,9 +189,18 @@ impl Road {
             length,
             cost_static: TravelCostStatic(length, length),
             cost_dynamic: TravelCostDynamic(0.0, 0.0),
+            forecast: (None, None),
         }
     }
 
+    pub fn id(&self) -> RoadId {
+        self.id
+    }
+
+    pub fn length(&self) -> f64 {
+        self.length
+    }
+
     pub fn cost_from(&self, n: &IntersectionId, use_dynamic: bool) -> f64 {
         if n.0 == self.id.0 {
             self.cost_static.0
@@ -101,6 +227,36 @@ impl Road {
             self.cost_dynamic.1 = cost
         }
     }
+
+    ///cost from `n` for a vehicle reaching the road `secs` from now, using the forecast dynamic
+    ///cost when there is one
+    ///
+    ///the forecast only covers the change in traffic, so it is shifted by however much the current
+    ///dynamic cost (which may include incident penalties) differs from its starting point
+    pub fn cost_from_at(&self, n: &IntersectionId, secs: f64) -> f64 {
+        let (forecast, current) = if n.0 == self.id.0 {
+            (&self.forecast.0, self.cost_dynamic.0)
+        } else {
+            (&self.forecast.1, self.cost_dynamic.1)
+        };
+        let dynamic = match forecast {
+            Some(f) => match (f.at(secs), f.costs.first()) {
+                (Some(at), Some(start)) => (current + at - start).max(0.0),
+                _ => current,
+            },
+            None => current,
+        };
+        self.cost_from(n, false) + dynamic
+    }
+
+    pub fn set_forecast(&mut self, forward: Option<CostForecast>, backward: Option<CostForecast>) {
+        if forward.is_some() {
+            self.forecast.0 = forward;
+        }
+        if backward.is_some() {
+            self.forecast.1 = backward;
+        }
+    }
 }
 
 impl Intersection {}
@@ -108,6 +264,10 @@ impl Intersection {}
 pub struct RoadMap {
     pub roads: HashMap<RoadId, Road>,
     intersections: HashMap<IntersectionId, Intersection>,
+    ///route with the forecast cost of each road at the time it is reached instead of the current one
+    pub use_forecast: bool,
+    ///lanes closed by hand, never routed over
+    closed: HashSet<LaneId>,
 }
 
 impl RoadMap {
@@ -115,6 +275,8 @@ impl RoadMap {
         Self {
             roads: HashMap::new(),
             intersections: HashMap::new(),
+            use_forecast: false,
+            closed: HashSet::new(),
         }
     }
 
@@ -147,6 +309,7 @@ impl RoadMap {
         curr_road: Option<&RoadId>,
         use_dynamic: bool,
         visited: &Vec<RoadId>,
+        elapsed: f64,
     ) -> (f64, Option<RoadId>) {
         if n1 == n2 {
             return (0.0, None);
@@ -160,8 +323,18 @@ impl RoadMap {
             if curr_road == Some(road_id) || visited.contains(&road_id) {
                 continue;
             }
+            let lane_id = LaneId(*road_id, if road_id.0 == n1.0 { 0 } else { 1 });
+            if self.closed.contains(&lane_id) {
+                continue;
+            }
             let road = self.roads.get(road_id).unwrap();
-            let cost = road.cost_from(&n1, use_dynamic)
+            let road_cost = if use_dynamic && self.use_forecast {
+                road.cost_from_at(&n1, elapsed)
+            } else {
+                road.cost_from(&n1, use_dynamic)
+            };
+            // costs are in meters, the next road is reached after driving them at free flow speed
+            let cost = road_cost
                 + self
                     .cost(
                         road.id.get_other_id(n1),
@@ -169,6 +342,7 @@ impl RoadMap {
                         Some(&road.id),
                         use_dynamic,
                         &[road.id].iter().chain(visited).map(|i| i.clone()).collect(),
+                        elapsed + road_cost / FREE_FLOW_SPEED,
                     )
                     .0;
             if cost < lowest_cost {
@@ -186,7 +360,7 @@ impl RoadMap {
         n2: IntersectionId,
         curr_road: Option<&RoadId>,
     ) -> (f64, Option<RoadId>) {
-        self.cost(n1, n2, curr_road, true, &vec![])
+        self.cost(n1, n2, curr_road, true, &vec![], 0.0)
     }
 
     pub fn shortest_direction(
@@ -195,7 +369,26 @@ impl RoadMap {
         n2: IntersectionId,
         curr_road: Option<&RoadId>,
     ) -> (f64, Option<RoadId>) {
-        self.cost(n1, n2, curr_road, false, &vec![])
+        self.cost(n1, n2, curr_road, false, &vec![], 0.0)
+    }
+
+    ///the boundary intersection (one with a single road) that traffic through `n` enters or leaves by,
+    ///`None` if `n` has no boundary neighbour or more than one
+    pub fn boundary_for(&self, n: IntersectionId) -> Option<IntersectionId> {
+        let node = self.intersections.get(&n)?;
+        if node.roads.len() == 1 {
+            return Some(n);
+        }
+        let mut boundaries = node.connections.iter().filter(|id| {
+            self.intersections
+                .get(id)
+                .map(|i| i.roads.len() == 1)
+                .unwrap_or(false)
+        });
+        match (boundaries.next(), boundaries.next()) {
+            (Some(id), None) => Some(*id),
+            _ => None,
+        }
     }
 
     pub fn road_length(&self, road_id: &RoadId) -> Option<f64> {
@@ -210,7 +403,39 @@ impl RoadMap {
         if let Some(road) = self.roads.get_mut(road_id) {
             road.set_cost(fwd, bck);
         } else {
-            println!("Failed to set road cost, no such road '{}'", road_id.0);
+            warn!("Failed to set road cost, no such road '{}'", road_id.0);
+        }
+    }
+
+    pub fn intersections(&self) -> impl Iterator<Item = &Intersection> {
+        self.intersections.values()
+    }
+
+    ///false if there is no such road
+    pub fn set_closed(&mut self, lane_id: LaneId, closed: bool) -> bool {
+        if !self.roads.contains_key(&lane_id.0) {
+            return false;
+        }
+        if closed {
+            self.closed.insert(lane_id);
+        } else {
+            self.closed.remove(&lane_id);
+        }
+        true
+    }
+
+    pub fn is_closed(&self, lane_id: &LaneId) -> bool {
+        self.closed.contains(lane_id)
+    }
+
+    pub fn set_forecast(
+        &mut self,
+        road_id: &RoadId,
+        fwd: Option<CostForecast>,
+        bck: Option<CostForecast>,
+    ) {
+        if let Some(road) = self.roads.get_mut(road_id) {
+            road.set_forecast(fwd, bck);
         }
     }
 }
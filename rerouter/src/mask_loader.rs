@@ -1,16 +1,18 @@
 use image::{self, ImageBuffer, Luma, LumaA};
+use log::debug;
 
 use crate::map::RoadId;
 
+use super::lane_geometry::LaneLocator;
 use super::map::LaneId;
 use std::collections::HashMap;
 use std::fs::{self, DirEntry, FileType};
 use std::path::Path;
 
-pub fn load_road_masks() -> HashMap<LaneId, ImageBuffer<LumaA<u8>, Vec<u8>>> {
+pub fn load_road_masks(dir: &Path) -> HashMap<LaneId, ImageBuffer<LumaA<u8>, Vec<u8>>> {
     let mut masks = HashMap::new();
 
-    for entry in fs::read_dir("../road-masks").expect("Failed to open road mask directory") {
+    for entry in fs::read_dir(dir).expect("Failed to open road mask directory") {
         if entry.is_err() {
             continue;
         }
@@ -56,8 +58,48 @@ pub fn load_road_masks() -> HashMap<LaneId, ImageBuffer<LumaA<u8>, Vec<u8>>> {
         let image = image.grayscale();
         let image = image.as_luma_alpha8().unwrap();
         masks.insert(LaneId(RoadId(int1id, int2id), lane_id), image.clone());
-        println!("Loaded {:?}", entry.path());
+        debug!("Loaded road mask {}", entry.path().display());
     }
 
     masks
 }
+
+///pixel masks loaded from `road-masks/<a>-<b>-<l|r>.png`
+pub struct RoadMasks(pub HashMap<LaneId, ImageBuffer<LumaA<u8>, Vec<u8>>>);
+
+impl LaneLocator for RoadMasks {
+    fn lane_ids(&self) -> Vec<LaneId> {
+        self.0.keys().copied().collect()
+    }
+
+    ///mask intensities of 200 and up are on the lane, scaled to 0..1 over that range
+    fn membership(&self, lane: &LaneId, pixel_pos: (f64, f64), _world_pos: (f64, f64)) -> f64 {
+        let mask = if let Some(mask) = self.0.get(lane) {
+            mask
+        } else {
+            return 0.0;
+        };
+        let (x, y) = (pixel_pos.0 as u32, pixel_pos.1 as u32);
+        if pixel_pos.0 < 0.0 || pixel_pos.1 < 0.0 || x >= mask.width() || y >= mask.height() {
+            return 0.0;
+        }
+        let intensity = mask.get_pixel(x, y).0[0];
+        if intensity < 200 {
+            return 0.0;
+        }
+        (intensity - 199) as f64 / 56.0
+    }
+
+    ///masks carry no direction, the tracker learns it from the vehicles on the lane instead
+    fn direction(&self, _lane: &LaneId, _world_pos: (f64, f64)) -> Option<f64> {
+        None
+    }
+
+    fn distance_along(&self, _lane: &LaneId, _world_pos: (f64, f64)) -> Option<f64> {
+        None
+    }
+
+    fn lane_length(&self, _lane: &LaneId) -> Option<f64> {
+        None
+    }
+}
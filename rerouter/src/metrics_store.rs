@@ -0,0 +1,447 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::map::{IntersectionId, LaneId, RoadId};
+
+///daily files older than this many days are deleted
+const RETENTION_DAYS: i64 = 30;
+const SECS_PER_DAY: i64 = 24 * 60 * 60;
+
+pub const LANE_HEADER: &str = "time,lane,vehicles,avg_speed,density,raw_cost,cost";
+pub const ROUTE_HEADER: &str = "time,road,intersection,destination,route";
+
+/// traffic on one lane at one point in time
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LaneRecord {
+    ///unix time in seconds
+    pub time: f64,
+    pub lane: LaneId,
+    pub vehicles: usize,
+    ///m/s, `None` without a settled speed estimate
+    pub avg_speed: Option<f64>,
+    ///passenger car equivalents per meter
+    pub density: f64,
+    pub raw_cost: f64,
+    ///dynamic cost used for routing
+    pub cost: f64,
+}
+
+impl LaneRecord {
+    pub fn to_csv(&self) -> String {
+        format!(
+            "{:.3},{},{},{},{:.4},{:.2},{:.2}",
+            self.time,
+            self.lane,
+            self.vehicles,
+            self.avg_speed
+                .map(|v| format!("{:.2}", v))
+                .unwrap_or_default(),
+            self.density,
+            self.raw_cost,
+            self.cost
+        )
+    }
+
+    fn from_csv(line: &str) -> Option<Self> {
+        let fields = line.split(',').collect::<Vec<&str>>();
+        if fields.len() != 7 {
+            return None;
+        }
+        Some(Self {
+            time: fields[0].parse().ok()?,
+            lane: fields[1].parse().ok()?,
+            vehicles: fields[2].parse().ok()?,
+            avg_speed: match fields[3] {
+                "" => None,
+                v => Some(v.parse().ok()?),
+            },
+            density: fields[4].parse().ok()?,
+            raw_cost: fields[5].parse().ok()?,
+            cost: fields[6].parse().ok()?,
+        })
+    }
+}
+
+/// the road a route indicator recommends on `road` at `intersection` for traffic to `destination`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RouteRecord {
+    ///unix time in seconds
+    pub time: f64,
+    pub road: RoadId,
+    pub intersection: IntersectionId,
+    pub destination: IntersectionId,
+    ///`None` when no route was found
+    pub route: Option<RoadId>,
+}
+
+impl RouteRecord {
+    pub fn to_csv(&self) -> String {
+        format!(
+            "{:.3},{},{},{},{}",
+            self.time,
+            self.road,
+            self.intersection,
+            self.destination,
+            self.route.map(|r| r.to_string()).unwrap_or_default()
+        )
+    }
+
+    fn from_csv(line: &str) -> Option<Self> {
+        let fields = line.split(',').collect::<Vec<&str>>();
+        if fields.len() != 5 {
+            return None;
+        }
+        Some(Self {
+            time: fields[0].parse().ok()?,
+            road: fields[1].parse().ok()?,
+            intersection: fields[2].parse().ok()?,
+            destination: fields[3].parse().ok()?,
+            route: match fields[4] {
+                "" => None,
+                r => Some(r.parse().ok()?),
+            },
+        })
+    }
+}
+
+pub fn unix_time(t: SystemTime) -> f64 {
+    t.duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs_f64()
+}
+
+///`YYYY-MM-DDTHH:MM:SS.mmmZ` (UTC) of a unix time
+pub fn format_time(time: f64) -> String {
+    let millis = (time * 1000.0).floor() as i64;
+    let secs = millis.div_euclid(1000);
+    let day_secs = secs.rem_euclid(86400);
+    format!(
+        "{}T{:02}:{:02}:{:02}.{:03}Z",
+        format_day(secs.div_euclid(86400)),
+        day_secs / 3600,
+        day_secs / 60 % 60,
+        day_secs % 60,
+        millis.rem_euclid(1000)
+    )
+}
+
+///`YYYY-MM-DD` (UTC) of the day `days` after the unix epoch
+fn format_day(days: i64) -> String {
+    // civil from days, http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", y, m, d)
+}
+
+///days after the unix epoch of a `YYYY-MM-DD` date
+fn parse_day(s: &str) -> Option<i64> {
+    let mut parts = s.splitn(3, '-').map(|p| p.parse::<i64>().ok());
+    let (y, m, d) = (parts.next()??, parts.next()??, parts.next()??);
+    if !(1..=12).contains(&m) || !(1..=31).contains(&d) {
+        return None;
+    }
+    // days from civil
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    Some(era * 146097 + doe - 719468)
+}
+
+///unix seconds, `YYYY-MM-DD` or `YYYY-MM-DDTHH:MM[:SS]` in UTC
+pub fn parse_time(s: &str) -> Option<f64> {
+    parse_time_in_day(s, false)
+}
+
+///as `parse_time` for the end of a time range, a bare date covers the whole day
+pub fn parse_end_time(s: &str) -> Option<f64> {
+    parse_time_in_day(s, true)
+}
+
+fn parse_time_in_day(s: &str, end_of_day: bool) -> Option<f64> {
+    if let Ok(secs) = s.parse::<f64>() {
+        return Some(secs);
+    }
+    let (date, time) = match s.split_once('T') {
+        Some((date, time)) => (date, Some(time)),
+        None => (s, None),
+    };
+    let mut secs = (parse_day(date)? * SECS_PER_DAY) as f64;
+    if time.is_none() && end_of_day {
+        // records are stored to the millisecond, this is the last one of the day
+        return Some(secs + SECS_PER_DAY as f64 - 0.001);
+    }
+    if let Some(time) = time {
+        let parts = time
+            .split(':')
+            .map(|p| p.parse::<f64>().ok())
+            .collect::<Option<Vec<f64>>>()?;
+        if parts.len() < 2 || parts.len() > 3 {
+            return None;
+        }
+        secs += parts[0] * 3600.0 + parts[1] * 60.0 + parts.get(2).unwrap_or(&0.0);
+    }
+    Some(secs)
+}
+
+/// lane metrics and route decisions appended to one csv file per kind and day (UTC), ie
+/// `lanes-2024-05-01.csv` and `routes-2024-05-01.csv`, keeping the last `RETENTION_DAYS` days
+pub struct MetricsStore {
+    dir: PathBuf,
+    interval: Duration,
+    last_write: Option<Instant>,
+}
+
+impl MetricsStore {
+    pub fn new<P: AsRef<Path>>(dir: P, interval: Duration) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            interval,
+            last_write: None,
+        }
+    }
+
+    ///whether a write interval has passed since the last write, starting a new one if so
+    pub fn due(&mut self, now: Instant) -> bool {
+        match self.last_write {
+            Some(t) if now.saturating_duration_since(t) < self.interval => false,
+            _ => {
+                self.last_write = Some(now);
+                true
+            }
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn path(&self, kind: &str, day: i64) -> PathBuf {
+        self.dir.join(format!("{}-{}.csv", kind, format_day(day)))
+    }
+
+    fn append(&self, kind: &str, header: &str, time: f64, lines: &[String]) -> io::Result<()> {
+        if lines.is_empty() {
+            return Ok(());
+        }
+        fs::create_dir_all(&self.dir)?;
+        let day = (time as i64).div_euclid(SECS_PER_DAY);
+        let path = self.path(kind, day);
+        let is_new = !path.exists();
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        if is_new {
+            // a new file gets its header before anything else can fail
+            file.write_all(format!("{}\n", header).as_bytes())?;
+            self.remove_old(kind, day)?;
+        }
+        let mut s = String::new();
+        for line in lines {
+            s += line;
+            s += "\n";
+        }
+        file.write_all(s.as_bytes())
+    }
+
+    ///deletes the files of `kind` from before the retention period
+    fn remove_old(&self, kind: &str, today: i64) -> io::Result<()> {
+        let prefix = format!("{}-", kind);
+        for entry in fs::read_dir(&self.dir)?.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let day = name
+                .strip_prefix(&prefix)
+                .and_then(|rest| rest.strip_suffix(".csv"))
+                .and_then(parse_day);
+            if let Some(day) = day {
+                if today - day >= RETENTION_DAYS {
+                    fs::remove_file(entry.path())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_lanes(&self, records: &[LaneRecord]) -> io::Result<()> {
+        let time = match records.first() {
+            Some(r) => r.time,
+            None => return Ok(()),
+        };
+        let lines = records.iter().map(|r| r.to_csv()).collect::<Vec<_>>();
+        self.append("lanes", LANE_HEADER, time, &lines)
+    }
+
+    pub fn write_routes(&self, records: &[RouteRecord]) -> io::Result<()> {
+        let time = match records.first() {
+            Some(r) => r.time,
+            None => return Ok(()),
+        };
+        let lines = records.iter().map(|r| r.to_csv()).collect::<Vec<_>>();
+        self.append("routes", ROUTE_HEADER, time, &lines)
+    }
+
+    ///lines of the daily files of `kind` covering `from..to` (unix seconds), headers left out
+    fn read_range(&self, kind: &str, from: f64, to: f64) -> io::Result<Vec<String>> {
+        if to < from {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "time range ends before it starts",
+            ));
+        }
+        let first = (from as i64).div_euclid(SECS_PER_DAY);
+        let last = (to as i64).div_euclid(SECS_PER_DAY);
+        let mut lines = vec![];
+        for day in first..=last {
+            match fs::read_to_string(self.path(kind, day)) {
+                Ok(contents) => lines.extend(contents.lines().skip(1).map(|l| l.to_string())),
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(lines)
+    }
+
+    ///lane records between `from` and `to` (unix seconds), of every lane if `lane` is `None`
+    pub fn query_lanes(
+        &self,
+        from: f64,
+        to: f64,
+        lane: Option<LaneId>,
+    ) -> io::Result<Vec<LaneRecord>> {
+        Ok(self
+            .read_range("lanes", from, to)?
+            .iter()
+            .filter_map(|l| LaneRecord::from_csv(l))
+            .filter(|r| r.time >= from && r.time <= to)
+            .filter(|r| lane.is_none_or(|lane| r.lane == lane))
+            .collect())
+    }
+
+    ///route decisions between `from` and `to` (unix seconds), of every indicator if `road` is `None`
+    pub fn query_routes(
+        &self,
+        from: f64,
+        to: f64,
+        road: Option<RoadId>,
+    ) -> io::Result<Vec<RouteRecord>> {
+        Ok(self
+            .read_range("routes", from, to)?
+            .iter()
+            .filter_map(|l| RouteRecord::from_csv(l))
+            .filter(|r| r.time >= from && r.time <= to)
+            .filter(|r| road.is_none_or(|road| r.road == road))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///an empty directory for one test, removed again when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("rerouter-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn lane_record(time: f64) -> LaneRecord {
+        LaneRecord {
+            time,
+            lane: "1-4-l".parse().unwrap(),
+            vehicles: 2,
+            avg_speed: Some(4.5),
+            density: 0.04,
+            raw_cost: 20.0,
+            cost: 21.5,
+        }
+    }
+
+    #[test]
+    fn days_round_trip() {
+        assert_eq!(parse_day("1970-01-01"), Some(0));
+        assert_eq!(parse_day("2000-03-01"), Some(11017));
+        assert_eq!(format_day(11017), "2000-03-01");
+        assert_eq!(format_day(-1), "1969-12-31");
+        for day in [-800, 0, 59, 60, 11016, 19782, 20000, 50000] {
+            assert_eq!(parse_day(&format_day(day)), Some(day));
+        }
+        assert_eq!(parse_day("2024-13-01"), None);
+        assert_eq!(parse_day("2024-01"), None);
+    }
+
+    #[test]
+    fn end_date_covers_the_day() {
+        let day = parse_time("2024-05-01").unwrap();
+        assert_eq!(parse_time("2024-05-01T12:30"), Some(day + 45000.0));
+        assert!(parse_end_time("2024-05-01").unwrap() > day + 86399.0);
+        assert!(parse_end_time("2024-05-01").unwrap() < day + 86400.0);
+        assert_eq!(parse_end_time("2024-05-01T12:30"), Some(day + 45000.0));
+    }
+
+    #[test]
+    fn queries_a_single_day() {
+        let dir = TempDir::new("query");
+        let store = MetricsStore::new(&dir.0, Duration::from_secs(1));
+        let day = parse_time("2024-05-01").unwrap();
+        store.write_lanes(&[lane_record(day + 3600.0)]).unwrap();
+        store.write_lanes(&[lane_record(day + 7200.0)]).unwrap();
+
+        let contents = fs::read_to_string(dir.0.join("lanes-2024-05-01.csv")).unwrap();
+        assert_eq!(contents.lines().next(), Some(LANE_HEADER));
+        let to = parse_end_time("2024-05-01").unwrap();
+        let records = store.query_lanes(day, to, None).unwrap();
+        assert_eq!(
+            records,
+            vec![lane_record(day + 3600.0), lane_record(day + 7200.0)]
+        );
+    }
+
+    #[test]
+    fn old_days_are_removed() {
+        let dir = TempDir::new("retention");
+        let store = MetricsStore::new(&dir.0, Duration::from_secs(1));
+        let today = parse_day("2024-05-31").unwrap();
+        for day in [today - RETENTION_DAYS, today - RETENTION_DAYS + 1] {
+            store
+                .write_lanes(&[lane_record((day * SECS_PER_DAY) as f64)])
+                .unwrap();
+        }
+        fs::write(dir.0.join("notes.txt"), "kept").unwrap();
+
+        store
+            .write_lanes(&[lane_record((today * SECS_PER_DAY) as f64)])
+            .unwrap();
+        let mut names = fs::read_dir(&dir.0)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(
+            names,
+            ["lanes-2024-05-02.csv", "lanes-2024-05-31.csv", "notes.txt"]
+        );
+    }
+}
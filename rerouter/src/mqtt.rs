@@ -0,0 +1,239 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use log::{error, info, warn};
+use rumqttc::{Client, Event, MqttOptions, Outgoing, Packet, Publish, QoS, RecvTimeoutError};
+use serde_json::json;
+
+use crate::api;
+use crate::config::Config;
+use crate::incident::Incident;
+use crate::map::{IntersectionId, RoadId, RoadMap};
+use crate::pipeline::{ControlCommand, Snapshot};
+use crate::schedule::Interval;
+
+const DEFAULT_PORT: u16 = 1883;
+///longest the client waits for broker traffic before checking for changes to publish
+const MQTT_POLL: Duration = Duration::from_millis(200);
+///wait before reconnecting after the broker connection fails
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+const KEEP_ALIVE: Duration = Duration::from_secs(10);
+///requests waiting to go out to the broker, publications are skipped once it is full
+const QUEUE_CAPACITY: usize = 64;
+
+///the host and port of a `host[:port]` broker address
+pub fn parse_broker(addr: &str) -> Result<(String, u16), String> {
+    let (host, port) = match addr.rsplit_once(':') {
+        Some((host, port)) => (
+            host,
+            port.parse()
+                .map_err(|e| format!("port '{}': {}", port, e))?,
+        ),
+        None => (addr, DEFAULT_PORT),
+    };
+    if host.is_empty() {
+        return Err(format!("no host in '{}'", addr));
+    }
+    Ok((host.to_string(), port))
+}
+
+#[derive(Debug, Clone)]
+struct Topics {
+    routes: String,
+    lanes: String,
+    incidents: String,
+    closures: String,
+    coefficients: String,
+}
+
+/// what was last published, so only changes are published again
+#[derive(Default)]
+struct Published {
+    ///indicator road, destination and decision of every route
+    routes: Vec<(RoadId, IntersectionId, Option<RoadId>)>,
+    incidents: HashMap<u64, Incident>,
+}
+
+/// publishes the pipeline state to an MQTT broker and takes control commands from it until
+/// `is_running` is cleared, reconnecting whenever the connection is lost
+///
+/// route decisions are published (retained) to `mqtt_routes_topic` when any of them changes,
+/// lane metrics to `mqtt_lanes_topic` every `mqtt_interval` and incidents to
+/// `mqtt_incidents_topic` as `{"event": "raised" | "cleared", "incident": {...}}`. the
+/// `mqtt_closures_topic` and `mqtt_coefficients_topic` messages are the bodies of the HTTP API's
+/// `POST /closures` and `POST /coefficients`
+///
+///`config.mqtt_broker` is expected to have been validated
+pub fn spawn(
+    config: &Config,
+    map: Arc<Mutex<RoadMap>>,
+    snapshot: Arc<Mutex<Snapshot>>,
+    control: Sender<ControlCommand>,
+    is_running: Arc<AtomicBool>,
+) -> JoinHandle<()> {
+    let broker = config.mqtt_broker.clone();
+    let (host, port) = parse_broker(&broker).expect("valid mqtt broker");
+    let mut options = MqttOptions::new(config.mqtt_client_id.clone(), host, port);
+    options.set_keep_alive(KEEP_ALIVE);
+    let topics = Topics {
+        routes: config.mqtt_routes_topic.clone(),
+        lanes: config.mqtt_lanes_topic.clone(),
+        incidents: config.mqtt_incidents_topic.clone(),
+        closures: config.mqtt_closures_topic.clone(),
+        coefficients: config.mqtt_coefficients_topic.clone(),
+    };
+    let mut lanes_interval = Interval::new(config.mqtt_interval);
+    info!("Connecting to MQTT broker {}", broker);
+
+    thread::spawn(move || {
+        let (client, mut connection) = Client::new(options, QUEUE_CAPACITY);
+        let mut published = Published::default();
+        let mut connected = false;
+        let mut failing = false;
+        while is_running.load(Ordering::Relaxed) {
+            match connection.recv_timeout(MQTT_POLL) {
+                Ok(Ok(Event::Incoming(Packet::ConnAck(_)))) => {
+                    info!("Connected to MQTT broker {}", broker);
+                    (connected, failing) = (true, false);
+                    for topic in [&topics.closures, &topics.coefficients] {
+                        if let Err(e) = client.try_subscribe(topic.as_str(), QoS::AtLeastOnce) {
+                            error!("Failed to subscribe to MQTT topic {}: {}", topic, e);
+                        }
+                    }
+                    // the broker may have lost the retained decisions
+                    published.routes.clear();
+                }
+                Ok(Ok(Event::Incoming(Packet::Publish(publish)))) => {
+                    handle(&publish, &topics, &map, &control)
+                }
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => {
+                    if connected {
+                        warn!("Lost MQTT broker {}: {}", broker, e);
+                    } else if !failing {
+                        warn!("Couldn't connect to MQTT broker {}: {}", broker, e);
+                    }
+                    (connected, failing) = (false, true);
+                    thread::sleep(RECONNECT_DELAY);
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            if connected {
+                let snapshot = snapshot.lock().unwrap();
+                publish(
+                    &client,
+                    &topics,
+                    &snapshot,
+                    &mut published,
+                    lanes_interval.due(Instant::now()),
+                );
+            }
+        }
+        if connected {
+            let _ = client.try_disconnect();
+            // runs the event loop until the queued publications and the disconnect are sent
+            while let Ok(Ok(event)) = connection.recv_timeout(MQTT_POLL) {
+                if let Event::Outgoing(Outgoing::Disconnect) = event {
+                    break;
+                }
+            }
+        }
+    })
+}
+
+///turns a message on a control topic into a command for the pipeline
+fn handle(
+    publish: &Publish,
+    topics: &Topics,
+    map: &Mutex<RoadMap>,
+    control: &Sender<ControlCommand>,
+) {
+    let body = String::from_utf8_lossy(&publish.payload);
+    let command = if publish.topic == topics.closures {
+        api::closure_command(&body, &map.lock().unwrap())
+    } else if publish.topic == topics.coefficients {
+        api::coefficients_command(&body)
+    } else {
+        return;
+    };
+    match command {
+        Ok(command) => {
+            let _ = control.send(command);
+        }
+        Err((_, e)) => warn!("Ignoring MQTT message on {}: {}", publish.topic, e),
+    }
+}
+
+///publishes the route decisions and incidents that changed, and the lane metrics with `lanes_due`
+fn publish(
+    client: &Client,
+    topics: &Topics,
+    snapshot: &Snapshot,
+    published: &mut Published,
+    lanes_due: bool,
+) {
+    let send = |topic: &str, qos: QoS, retain: bool, payload: String| {
+        if let Err(e) = client.try_publish(topic, qos, retain, payload) {
+            warn!("Failed to publish to MQTT topic {}: {}", topic, e);
+        }
+    };
+
+    let routes = snapshot
+        .routes
+        .iter()
+        .map(|r| (r.road, r.destination, r.route))
+        .collect::<Vec<_>>();
+    // nothing is decided before the first cost update
+    if !routes.is_empty() && routes != published.routes {
+        send(
+            &topics.routes,
+            QoS::AtLeastOnce,
+            true,
+            json!(snapshot.routes).to_string(),
+        );
+        published.routes = routes;
+    }
+
+    for incident in &snapshot.incidents {
+        if let Entry::Vacant(entry) = published.incidents.entry(incident.id) {
+            let event = json!({ "event": "raised", "incident": incident });
+            send(
+                &topics.incidents,
+                QoS::AtLeastOnce,
+                false,
+                event.to_string(),
+            );
+            entry.insert(incident.clone());
+        }
+    }
+    published.incidents.retain(|id, incident| {
+        let active = snapshot.incidents.iter().any(|i| i.id == *id);
+        if !active {
+            let event = json!({ "event": "cleared", "incident": incident });
+            send(
+                &topics.incidents,
+                QoS::AtLeastOnce,
+                false,
+                event.to_string(),
+            );
+        }
+        active
+    });
+
+    if lanes_due && !snapshot.lanes.is_empty() {
+        let mut lanes = snapshot.lanes.clone();
+        lanes.sort_by_key(|l| l.lane);
+        send(
+            &topics.lanes,
+            QoS::AtMostOnce,
+            false,
+            json!(lanes).to_string(),
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::map::{IntersectionId, RoadMap};
+use crate::vehicle_tracker::Trip;
+
+///number of closed windows kept in `OdMatrix::history`
+const HISTORY_LEN: usize = 48;
+
+///the boundary intersections a trip most likely entered and left the network by
+///
+///vehicles are usually only seen on the inner roads, so the origin is taken as the boundary
+///next to where the first lane starts and the destination as the one next to where the last lane ends
+pub fn trip_endpoints(trip: &Trip, map: &RoadMap) -> Option<(IntersectionId, IntersectionId)> {
+    let origin = map.boundary_for(trip.lanes.first()?.entry_id())?;
+    let destination = map.boundary_for(trip.lanes.last()?.exit_id())?;
+    Some((origin, destination))
+}
+
+/// trip counts between boundary intersections over one time window
+#[derive(Debug, Clone)]
+pub struct OdWindow {
+    pub start: Instant,
+    pub end: Instant,
+    pub counts: HashMap<(IntersectionId, IntersectionId), usize>,
+    ///summed duration of the trips between each pair, in seconds
+    pub trip_secs: HashMap<(IntersectionId, IntersectionId), f64>,
+    ///trips whose origin or destination could not be worked out
+    pub unresolved: usize,
+}
+
+impl OdWindow {
+    fn new(start: Instant) -> Self {
+        Self {
+            start,
+            end: start,
+            counts: HashMap::new(),
+            trip_secs: HashMap::new(),
+            unresolved: 0,
+        }
+    }
+
+    ///average time the trips between `origin` and `destination` were tracked for, in seconds
+    pub fn mean_trip_secs(
+        &self,
+        origin: IntersectionId,
+        destination: IntersectionId,
+    ) -> Option<f64> {
+        let count = *self.counts.get(&(origin, destination))?;
+        Some(self.trip_secs.get(&(origin, destination))? / count as f64)
+    }
+
+    pub fn total(&self) -> usize {
+        self.counts.values().sum()
+    }
+
+    ///origins as rows and destinations as columns
+    pub fn format_table(&self) -> String {
+        let ids = self
+            .counts
+            .keys()
+            .flat_map(|(o, d)| [o.0, d.0])
+            .collect::<BTreeSet<u32>>()
+            .into_iter()
+            .map(IntersectionId)
+            .collect::<Vec<IntersectionId>>();
+
+        let mut s = String::from("o\\d");
+        for d in &ids {
+            s += &format!("{:>5}", d.to_string());
+        }
+        for o in &ids {
+            s += &format!("\n{:<3}", o.to_string());
+            for d in &ids {
+                s += &format!("{:>5}", self.counts.get(&(*o, *d)).unwrap_or(&0));
+            }
+            for d in &ids {
+                if let Some(secs) = self.mean_trip_secs(*o, *d) {
+                    s += &format!("  {}->{} {:.1}s", o, d, secs);
+                }
+            }
+        }
+        s += &format!("\n{} trips, {} unresolved", self.total(), self.unresolved);
+        s
+    }
+}
+
+/// origin-destination matrix built from the trips of tracked vehicles over fixed time windows
+pub struct OdMatrix {
+    window: Duration,
+    pub current: OdWindow,
+    ///closed windows, oldest first
+    pub history: VecDeque<OdWindow>,
+}
+
+impl OdMatrix {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            current: OdWindow::new(Instant::now()),
+            history: VecDeque::new(),
+        }
+    }
+
+    pub fn record(&mut self, trip: &Trip, map: &RoadMap) {
+        match trip_endpoints(trip, map) {
+            Some(od) => {
+                *self.current.counts.entry(od).or_insert(0) += 1;
+                *self.current.trip_secs.entry(od).or_insert(0.0) +=
+                    trip.end.duration_since(trip.start).as_secs_f64();
+            }
+            None => self.current.unresolved += 1,
+        }
+    }
+
+    ///closes the current window once it has run for the configured duration and returns it
+    pub fn update(&mut self, now: Instant) -> Option<&OdWindow> {
+        if now.saturating_duration_since(self.current.start) < self.window {
+            return None;
+        }
+        let mut closed = std::mem::replace(&mut self.current, OdWindow::new(now));
+        closed.end = now;
+        self.history.push_back(closed);
+        if self.history.len() > HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.back()
+    }
+}
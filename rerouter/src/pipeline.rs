@@ -0,0 +1,444 @@
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use log::{error, info, warn};
+use serde::Serialize;
+use traffic_cost::{CostModel, CostParams};
+
+use crate::config::Config;
+use crate::cost_smoothing::CostSmoother;
+use crate::forecast::CostForecaster;
+use crate::incident::{Incident, IncidentDetector};
+use crate::light_controller::{Led, LedCommand};
+use crate::map::{LaneId, RoadMap};
+use crate::metrics_store::{self, LaneRecord, MetricsStore, RouteRecord};
+use crate::od_matrix::OdMatrix;
+use crate::presets;
+use crate::route_indicator::RouteIndicator;
+use crate::schedule::Interval;
+use crate::telemetry::Telemetry;
+use crate::turning_movements::TurningMovements;
+use crate::vehicle_class::VehicleClass;
+use crate::vehicle_tracker::{Tracker, VehicleId};
+use crate::RxDatas;
+
+///interval lost vehicles are timed out and finished trips counted at
+const TRACKER_INTERVAL: Duration = Duration::from_millis(50);
+///interval lane costs are worked out and route decisions made at
+const COST_INTERVAL: Duration = Duration::from_millis(200);
+///length of the time windows the origin-destination matrix is counted over
+const OD_WINDOW: Duration = Duration::from_secs(15 * 60);
+///length of the time bins turning movements are counted over
+const TURNING_BIN: Duration = Duration::from_secs(15 * 60);
+///interval lane metrics and route decisions are stored at
+pub const METRICS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// a change to the running pipeline, made through the HTTP API or another control interface
+#[derive(Debug, Clone)]
+pub enum ControlCommand {
+    ///changes the given cost coefficients, leaving the rest
+    SetCoefficients {
+        density_coeff: Option<f64>,
+        vel_coeff: Option<f64>,
+        clearance_coeff: Option<f64>,
+        queue_coeff: Option<f64>,
+    },
+    SetClosed {
+        lane: LaneId,
+        closed: bool,
+    },
+    ///fixes the dynamic cost of a lane, `None` hands it back to the tracker
+    OverrideCost {
+        lane: LaneId,
+        cost: Option<f64>,
+    },
+}
+
+/// a tracked vehicle as other threads see it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VehicleState {
+    pub id: VehicleId,
+    ///camera pixels
+    pub pos: (f64, f64),
+    ///map coordinates in meters
+    pub world_pos: (f64, f64),
+    ///m/s
+    pub speed: f64,
+    pub lane: LaneId,
+    pub class: VehicleClass,
+    ///0..1, how settled the motion estimate is
+    pub confidence: f64,
+}
+
+/// the stages a lane's dynamic cost goes through, to inspect the smoothing and forecasts with
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LaneCosts {
+    pub lane: LaneId,
+    ///cost model output before smoothing
+    pub raw: Option<f64>,
+    pub smoothed: Option<f64>,
+    ///latest forecast, `forecast_step` seconds apart
+    pub forecast: Vec<f64>,
+    pub forecast_step: Option<f64>,
+    ///median traversal time in seconds
+    pub travel_time: Option<f64>,
+    ///traversals the travel time is taken over
+    pub traversals: usize,
+}
+
+/// the state of the pipeline after its latest updates, for readers on other threads
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot {
+    ///updated with every tracker update, the rest with every cost update
+    pub vehicles: Vec<VehicleState>,
+    pub lanes: Vec<LaneRecord>,
+    pub lane_costs: Vec<LaneCosts>,
+    ///current decision of every route indicator
+    pub routes: Vec<RouteRecord>,
+    pub leds: Vec<Led>,
+    ///active incidents, oldest first
+    pub incidents: Vec<Incident>,
+    pub cost_params: CostParams,
+    pub cost_model: &'static str,
+    ///lanes with a manually fixed cost
+    pub cost_overrides: HashMap<LaneId, f64>,
+}
+
+/// everything between the detections and the road costs the light controller routes with,
+/// kept apart from the dashboard so the rerouter can also run headless
+pub struct Pipeline {
+    pub map: Arc<Mutex<RoadMap>>,
+    pub tracker: Tracker,
+    pub od_matrix: OdMatrix,
+    pub incidents: IncidentDetector,
+    pub turning_movements: TurningMovements,
+    pub forecaster: CostForecaster,
+    pub metrics: MetricsStore,
+    pub cost_smoother: CostSmoother,
+    pub cost_model: Box<dyn CostModel>,
+    pub cost_params: CostParams,
+    pub route_indicators: Vec<RouteIndicator>,
+    ///lanes whose dynamic cost was fixed by hand
+    pub cost_overrides: HashMap<LaneId, f64>,
+    tracker_interval: Interval,
+    cost_interval: Interval,
+    ///receives the leds to light whenever the route decisions change
+    controller: Sender<LedCommand>,
+    lit_leds: Option<Vec<Led>>,
+    commands: Receiver<ControlCommand>,
+    command_sender: Sender<ControlCommand>,
+    snapshot: Arc<Mutex<Snapshot>>,
+    telemetry: Arc<Telemetry>,
+}
+
+impl Pipeline {
+    ///`config` is expected to have been validated
+    pub fn new(
+        map: Arc<Mutex<RoadMap>>,
+        controller: Sender<LedCommand>,
+        telemetry: Arc<Telemetry>,
+        config: &Config,
+    ) -> Self {
+        let cost_model = traffic_cost::from_name(&config.cost_model).expect("known cost model");
+        let cost_smoother =
+            CostSmoother::from_spec(&config.cost_smoothing).expect("valid cost smoothing");
+        info!("Smoothing lane costs with {}", cost_smoother.default);
+        let (command_sender, commands) = mpsc::channel();
+        let tracker = Tracker::new(config, &map.lock().unwrap());
+
+        Self {
+            map,
+            tracker,
+            od_matrix: OdMatrix::new(OD_WINDOW),
+            incidents: IncidentDetector::new(),
+            turning_movements: TurningMovements::new(TURNING_BIN),
+            forecaster: CostForecaster::new(),
+            metrics: MetricsStore::new(&config.metrics_dir, METRICS_INTERVAL),
+            cost_smoother,
+            cost_model,
+            cost_params: config.cost_params,
+            route_indicators: presets::create_route_indicators(),
+            cost_overrides: HashMap::new(),
+            tracker_interval: Interval::new(TRACKER_INTERVAL),
+            cost_interval: Interval::new(COST_INTERVAL),
+            controller,
+            lit_leds: None,
+            commands,
+            command_sender,
+            snapshot: Arc::new(Mutex::new(Snapshot::default())),
+            telemetry,
+        }
+    }
+
+    ///sends commands that are applied the next time the pipeline drains its input
+    pub fn control(&self) -> Sender<ControlCommand> {
+        self.command_sender.clone()
+    }
+
+    ///updated after every cost update
+    pub fn snapshot(&self) -> Arc<Mutex<Snapshot>> {
+        Arc::clone(&self.snapshot)
+    }
+
+    pub fn apply(&mut self, command: ControlCommand) {
+        info!("Applying {:?}", command);
+        match command {
+            ControlCommand::SetCoefficients {
+                density_coeff,
+                vel_coeff,
+                clearance_coeff,
+                queue_coeff,
+            } => {
+                let params = &mut self.cost_params;
+                params.density_coeff = density_coeff.unwrap_or(params.density_coeff);
+                params.vel_coeff = vel_coeff.unwrap_or(params.vel_coeff);
+                params.clearance_coeff = clearance_coeff.unwrap_or(params.clearance_coeff);
+                params.queue_coeff = queue_coeff.unwrap_or(params.queue_coeff);
+            }
+            ControlCommand::SetClosed { lane, closed } => {
+                if !self.map.lock().unwrap().set_closed(lane, closed) {
+                    warn!("Failed to close lane, no such road '{}'", lane.0);
+                }
+            }
+            ControlCommand::OverrideCost { lane, cost } => match cost {
+                Some(cost) => {
+                    self.cost_overrides.insert(lane, cost);
+                }
+                None => {
+                    self.cost_overrides.remove(&lane);
+                }
+            },
+        }
+    }
+
+    pub fn ingest(&mut self, datas: RxDatas) {
+        for d in datas.0 {
+            self.tracker.on_recv(d)
+        }
+    }
+
+    ///ingests every detection waiting on `rx` and applies the waiting commands without blocking,
+    ///false once the detector is gone
+    pub fn drain(&mut self, rx: &Receiver<RxDatas>) -> bool {
+        while let Ok(command) = self.commands.try_recv() {
+            self.apply(command);
+        }
+        loop {
+            match rx.try_recv() {
+                Ok(d) => self.ingest(d),
+                Err(TryRecvError::Empty) => return true,
+                Err(TryRecvError::Disconnected) => return false,
+            }
+        }
+    }
+
+    ///runs the tracker and cost updates whose intervals have passed
+    pub fn run_due(&mut self, now: Instant) {
+        if self.tracker_interval.due(now) {
+            self.update_tracker();
+        }
+        if self.cost_interval.due(now) {
+            self.update_costs(false);
+        }
+    }
+
+    ///stores the final lane metrics and route decisions whether or not they are due
+    pub fn finish(&mut self) {
+        self.update_costs(true);
+    }
+
+    ///when the next update is due
+    pub fn next_due(&self) -> Instant {
+        self.tracker_interval.next().min(self.cost_interval.next())
+    }
+
+    ///works out the dynamic cost of every tracked lane and hands it to the map, storing metrics
+    ///(always with `store_metrics`) and refitting the forecasts when they are due
+    fn update_costs(&mut self, store_metrics: bool) {
+        let mut map = self.map.lock().unwrap();
+        self.incidents.update(&self.tracker, &map);
+        let now = Instant::now();
+        let time = metrics_store::unix_time(SystemTime::now());
+        let mut lane_records = vec![];
+        for (lane_id, lane) in &self.tracker.lanes {
+            let road_len = map.road_length(&lane_id.0).unwrap();
+            let raw_cost = self
+                .tracker
+                .lane_dynamic_cost(
+                    lane_id,
+                    road_len,
+                    self.cost_model.as_ref(),
+                    &self.cost_params,
+                )
+                .unwrap();
+            // incidents are deliberate step changes, so they are left out of the smoothing
+            let smoothed = self.cost_smoother.update(*lane_id, raw_cost, now);
+            self.forecaster.observe(*lane_id, smoothed);
+            let cost = match self.cost_overrides.get(lane_id) {
+                Some(cost) => *cost,
+                None => smoothed + self.incidents.penalty(lane_id),
+            };
+            lane_records.push(LaneRecord {
+                time,
+                lane: *lane_id,
+                vehicles: lane.len(),
+                avg_speed: self.tracker.lane_avg_speed(lane_id),
+                density: self.tracker.lane_pce(lane_id).unwrap_or(0.0) / road_len,
+                raw_cost,
+                cost,
+            });
+
+            if lane_id.1 == 0 {
+                map.set_cost(&lane_id.0, Some(cost), None);
+            } else {
+                map.set_cost(&lane_id.0, None, Some(cost));
+            }
+        }
+
+        if self.forecaster.update(now) {
+            for (lane_id, forecast) in &self.forecaster.forecasts {
+                if lane_id.1 == 0 {
+                    map.set_forecast(&lane_id.0, Some(forecast.clone()), None);
+                } else {
+                    map.set_forecast(&lane_id.0, None, Some(forecast.clone()));
+                }
+            }
+        }
+
+        let mut route_records = vec![];
+        let mut leds = vec![];
+        for indicator in &self.route_indicators {
+            for (destination, road_leds) in &indicator.routes {
+                let (_, route) =
+                    map.best_direction(indicator.int_id, *destination, Some(&indicator.road_id));
+                if let Some(led) = route.and_then(|road| road_leds.get(&road)) {
+                    leds.push(*led);
+                }
+                route_records.push(RouteRecord {
+                    time,
+                    road: indicator.road_id,
+                    intersection: indicator.int_id,
+                    destination: *destination,
+                    route,
+                });
+            }
+        }
+        drop(map);
+
+        if self.metrics.due(now) || store_metrics {
+            if let Err(e) = self
+                .metrics
+                .write_lanes(&lane_records)
+                .and_then(|_| self.metrics.write_routes(&route_records))
+            {
+                error!(
+                    "Failed to store metrics in {}: {}",
+                    self.metrics.dir().display(),
+                    e
+                );
+            }
+        }
+
+        if self.lit_leds.as_ref() != Some(&leds) {
+            let _ = self.controller.send(LedCommand::Show(leds.clone()));
+            self.lit_leds = Some(leds.clone());
+        }
+
+        let mut snapshot = self.snapshot.lock().unwrap();
+        let changes = route_records
+            .iter()
+            .filter(|new| {
+                snapshot.routes.iter().any(|old| {
+                    (old.road, old.destination) == (new.road, new.destination)
+                        && old.route != new.route
+                })
+            })
+            .count();
+        self.telemetry
+            .route_changes
+            .fetch_add(changes as u64, Ordering::Relaxed);
+        snapshot.lanes = lane_records;
+        snapshot.lane_costs = self
+            .tracker
+            .lanes
+            .keys()
+            .map(|lane| self.lane_costs(lane))
+            .collect();
+        snapshot.routes = route_records;
+        snapshot.leds = leds;
+        snapshot.incidents = self.incidents.incidents.values().cloned().collect();
+        snapshot.incidents.sort_by_key(|i| i.id);
+        snapshot.cost_params = self.cost_params;
+        snapshot.cost_model = self.cost_model.name();
+        snapshot.cost_overrides = self.cost_overrides.clone();
+    }
+
+    fn lane_costs(&self, lane: &LaneId) -> LaneCosts {
+        let forecast = self.forecaster.forecast(lane);
+        LaneCosts {
+            lane: *lane,
+            raw: self.cost_smoother.raw(lane),
+            smoothed: self.cost_smoother.smoothed(lane),
+            forecast: forecast.map(|f| f.costs.clone()).unwrap_or_default(),
+            forecast_step: forecast.map(|f| f.step.as_secs_f64()),
+            travel_time: self.tracker.lane_travel_time(lane),
+            traversals: self
+                .tracker
+                .travel_times
+                .get(lane)
+                .map(|t| t.count())
+                .unwrap_or(0),
+        }
+    }
+
+    ///times out lost vehicles and counts the trips and movements that finished since the last update
+    fn update_tracker(&mut self) {
+        self.tracker.update();
+        self.telemetry
+            .dropped_detections
+            .store(self.tracker.dropped_detections as u64, Ordering::Relaxed);
+        self.snapshot.lock().unwrap().vehicles = self
+            .tracker
+            .vehicles
+            .values()
+            .map(|v| VehicleState {
+                id: v.id,
+                pos: v.pos,
+                world_pos: v.world_pos,
+                speed: v.avg_vel,
+                lane: v.lane_id,
+                class: v.vehicle_class(),
+                confidence: v.filter.confidence(),
+            })
+            .collect();
+
+        let transitions = self.tracker.take_transitions();
+        self.forecaster.record_transitions(&transitions);
+        if !transitions.is_empty() {
+            let map = self.map.lock().unwrap();
+            for transition in &transitions {
+                self.turning_movements
+                    .record(transition, &self.route_indicators, &map);
+            }
+        }
+
+        // after the transitions, a trip's last movements are checked against the indicators too
+        let trips = self.tracker.take_finished_trips();
+        if !trips.is_empty() {
+            let map = self.map.lock().unwrap();
+            for trip in &trips {
+                self.od_matrix.record(trip, &map);
+                self.turning_movements.record_trip(trip, &map);
+            }
+        }
+        if let Some(window) = self.od_matrix.update(Instant::now()) {
+            info!("origin-destination matrix:\n{}", window.format_table());
+        }
+        if let Some(bin) = self.turning_movements.update(Instant::now()) {
+            info!("turning movements:\n{}", bin.format_table());
+        }
+    }
+}
@@ -0,0 +1,66 @@
+///vehicles slower than this (m/s) count as stopped
+pub const STOPPED_SPEED: f64 = 0.5;
+///a queue starts with a stopped vehicle at most this far (m) from the downstream end of the lane
+const QUEUE_HEAD_DIST: f64 = 6.0;
+///largest gap (m) between two stopped vehicles of the same queue
+const QUEUE_MAX_GAP: f64 = 8.0;
+///space (m) a queued vehicle takes up, used when positions along the lane are unknown
+const JAM_SPACING: f64 = 7.0;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct QueueEstimate {
+    ///stopped or slow vehicles anywhere on the lane
+    pub stopped: usize,
+    ///stopped vehicles in the queue at the downstream end
+    pub queued: usize,
+    ///distance (m) from the downstream end of the lane back to the last queued vehicle
+    pub length: f64,
+}
+
+///`vehicles` holds the distance along the lane (if known) and speed of every vehicle on a lane
+///of `lane_length` meters
+///
+///without positions along the lane (ie, road masks instead of lane geometry) every stopped
+///vehicle is assumed to be queued, `JAM_SPACING` apart
+pub fn estimate_queue(vehicles: &[(Option<f64>, f64)], lane_length: f64) -> QueueEstimate {
+    let stopped = vehicles
+        .iter()
+        .filter(|(_, speed)| *speed < STOPPED_SPEED)
+        .collect::<Vec<_>>();
+
+    if stopped.iter().any(|(pos, _)| pos.is_none()) {
+        return QueueEstimate {
+            stopped: stopped.len(),
+            queued: stopped.len(),
+            length: (stopped.len() as f64 * JAM_SPACING).min(lane_length),
+        };
+    }
+
+    // distance to the downstream end, closest first
+    let mut dists = stopped
+        .iter()
+        .map(|(pos, _)| (lane_length - pos.unwrap()).max(0.0))
+        .collect::<Vec<f64>>();
+    dists.sort_by(|a, b| a.total_cmp(b));
+
+    let mut queued = 0;
+    let mut length = 0.0;
+    for dist in dists {
+        let limit = if queued == 0 {
+            QUEUE_HEAD_DIST
+        } else {
+            length + QUEUE_MAX_GAP
+        };
+        if dist > limit {
+            break;
+        }
+        queued += 1;
+        length = dist;
+    }
+
+    QueueEstimate {
+        stopped: stopped.len(),
+        queued,
+        length,
+    }
+}
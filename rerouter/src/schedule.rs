@@ -0,0 +1,34 @@
+use std::time::{Duration, Instant};
+
+/// a job that runs every `period`, missed runs are skipped rather than caught up on
+#[derive(Debug, Clone, Copy)]
+pub struct Interval {
+    period: Duration,
+    next: Instant,
+}
+
+impl Interval {
+    ///first due straight away
+    pub fn new(period: Duration) -> Self {
+        Self {
+            period,
+            next: Instant::now(),
+        }
+    }
+
+    ///whether the job should run at `now`, moving on to the next run if so
+    pub fn due(&mut self, now: Instant) -> bool {
+        if now < self.next {
+            return false;
+        }
+        self.next += self.period;
+        if self.next <= now {
+            self.next = now + self.period;
+        }
+        true
+    }
+
+    pub fn next(&self) -> Instant {
+        self.next
+    }
+}
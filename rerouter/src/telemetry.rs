@@ -0,0 +1,131 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::map::LaneId;
+use crate::pipeline::Snapshot;
+
+/// counters shared by the threads of the rerouter, served in the Prometheus text format
+#[derive(Debug, Default)]
+pub struct Telemetry {
+    ///detection records read from the detector
+    pub detections: AtomicU64,
+    ///detection records the tracker ignored, from a retired id or outside every lane
+    pub dropped_detections: AtomicU64,
+    ///detector output that wasn't a valid detection record
+    pub parse_errors: AtomicU64,
+    pub detector_restarts: AtomicU64,
+    ///changes of the decision of any route indicator
+    pub route_changes: AtomicU64,
+    pub serial_writes: AtomicU64,
+    ///writes that failed or weren't answered by the light controller
+    pub serial_write_failures: AtomicU64,
+    ///total time spent on serial writes, including waiting for the answer
+    serial_write_nanos: AtomicU64,
+}
+
+impl Telemetry {
+    pub fn record_serial_write(&self, latency: Duration, ok: bool) {
+        self.serial_writes.fetch_add(1, Ordering::Relaxed);
+        self.serial_write_nanos
+            .fetch_add(latency.as_nanos() as u64, Ordering::Relaxed);
+        if !ok {
+            self.serial_write_failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    ///the counters and the lane gauges of `snapshot` in the Prometheus text exposition format
+    pub fn render(&self, snapshot: &Snapshot) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, f64)]| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            for (labels, value) in samples {
+                let _ = writeln!(out, "{}{} {}", name, labels, value);
+            }
+        };
+        let counter = |c: &AtomicU64| [(String::new(), c.load(Ordering::Relaxed) as f64)];
+
+        metric(
+            "rerouter_detections_received_total",
+            "counter",
+            "Detection records read from the vehicle detector.",
+            &counter(&self.detections),
+        );
+        metric(
+            "rerouter_detections_dropped_total",
+            "counter",
+            "Detection records ignored by the tracker, from a retired id or outside every lane.",
+            &counter(&self.dropped_detections),
+        );
+        metric(
+            "rerouter_detection_parse_errors_total",
+            "counter",
+            "Vehicle detector output that was not a valid detection record.",
+            &counter(&self.parse_errors),
+        );
+        metric(
+            "rerouter_detector_restarts_total",
+            "counter",
+            "Times the vehicle detector was restarted after exiting.",
+            &counter(&self.detector_restarts),
+        );
+        metric(
+            "rerouter_route_changes_total",
+            "counter",
+            "Changes of the decision of any route indicator.",
+            &counter(&self.route_changes),
+        );
+        metric(
+            "rerouter_serial_write_failures_total",
+            "counter",
+            "Writes to the light controller that failed or were not answered.",
+            &counter(&self.serial_write_failures),
+        );
+        metric(
+            "rerouter_serial_write_seconds",
+            "summary",
+            "Time taken by writes to the light controller, including waiting for the answer.",
+            // the sample suffixes go where the labels would
+            &[
+                (
+                    "_sum".to_string(),
+                    self.serial_write_nanos.load(Ordering::Relaxed) as f64 / 1e9,
+                ),
+                (
+                    "_count".to_string(),
+                    self.serial_writes.load(Ordering::Relaxed) as f64,
+                ),
+            ],
+        );
+
+        let mut lanes = snapshot.lanes.iter().collect::<Vec<_>>();
+        lanes.sort_by_key(|l| l.lane);
+        let lane_labels = |lane: &LaneId| {
+            format!(
+                "{{road=\"{}\",direction=\"{}\"}}",
+                lane.0,
+                if lane.1 == 0 { "l" } else { "r" }
+            )
+        };
+        metric(
+            "rerouter_lane_vehicles",
+            "gauge",
+            "Vehicles tracked on each lane.",
+            &lanes
+                .iter()
+                .map(|l| (lane_labels(&l.lane), l.vehicles as f64))
+                .collect::<Vec<_>>(),
+        );
+        metric(
+            "rerouter_lane_cost",
+            "gauge",
+            "Dynamic cost routed with on each road and direction.",
+            &lanes
+                .iter()
+                .map(|l| (lane_labels(&l.lane), l.cost))
+                .collect::<Vec<_>>(),
+        );
+        out
+    }
+}
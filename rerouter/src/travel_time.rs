@@ -0,0 +1,51 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+///how long observed traversals are kept in the rolling statistics
+const WINDOW: Duration = Duration::from_secs(300);
+///traversals shorter than this (s) come from lane assignment flicker at lane boundaries
+const MIN_TRAVEL_TIME: f64 = 0.2;
+
+/// rolling statistics of the observed time (seconds) vehicles took to traverse a lane
+#[derive(Debug, Default)]
+pub struct TravelTimes {
+    samples: VecDeque<(Instant, f64)>,
+}
+
+impl TravelTimes {
+    pub fn record(&mut self, finished: Instant, secs: f64) {
+        if secs < MIN_TRAVEL_TIME {
+            return;
+        }
+        self.samples.push_back((finished, secs));
+        self.prune(finished);
+    }
+
+    ///drops samples older than the rolling window
+    pub fn prune(&mut self, now: Instant) {
+        while let Some((finished, _)) = self.samples.front() {
+            if now.saturating_duration_since(*finished) <= WINDOW {
+                break;
+            }
+            self.samples.pop_front();
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn median(&self) -> Option<f64> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let mut times = self.samples.iter().map(|(_, t)| *t).collect::<Vec<f64>>();
+        times.sort_by(|a, b| a.total_cmp(b));
+        let mid = times.len() / 2;
+        Some(if times.len() % 2 == 0 {
+            (times[mid - 1] + times[mid]) / 2.0
+        } else {
+            times[mid]
+        })
+    }
+}
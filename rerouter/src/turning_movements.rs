@@ -0,0 +1,320 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::map::{IntersectionId, RoadId, RoadMap};
+use crate::od_matrix::trip_endpoints;
+use crate::route_indicator::RouteIndicator;
+use crate::vehicle_tracker::{LaneTransition, Trip, VehicleId};
+
+///number of closed bins kept in `TurningMovements::history`
+const HISTORY_LEN: usize = 96;
+
+/// a movement through `intersection`, arriving from `from` and leaving towards `to`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Movement {
+    pub intersection: IntersectionId,
+    pub from: IntersectionId,
+    pub to: IntersectionId,
+}
+
+impl Movement {
+    ///the road the movement arrives by
+    pub fn approach(&self, map: &RoadMap) -> Option<RoadId> {
+        road_between(map, self.from, self.intersection)
+    }
+
+    ///the road the movement leaves by
+    pub fn exit(&self, map: &RoadMap) -> Option<RoadId> {
+        road_between(map, self.intersection, self.to)
+    }
+}
+
+fn road_between(map: &RoadMap, n1: IntersectionId, n2: IntersectionId) -> Option<RoadId> {
+    [RoadId(n1.0, n2.0), RoadId(n2.0, n1.0)]
+        .into_iter()
+        .find(|road_id| map.roads.contains_key(road_id))
+}
+
+///the movement a lane transition makes
+///
+///a vehicle's first detection counts as a movement from the boundary road next to where its lane
+///starts, since vehicles are usually only seen once they have turned onto the inner roads
+pub fn transition_movement(transition: &LaneTransition, map: &RoadMap) -> Option<Movement> {
+    let to = transition.to;
+    match transition.from {
+        Some(from) => {
+            // the lane directions may be wrong, so use whichever end the two roads share
+            let intersection = [from.exit_id(), from.entry_id()]
+                .into_iter()
+                .find(|n| n.0 == to.0 .0 || n.0 == to.0 .1)?;
+            Some(Movement {
+                intersection,
+                from: from.0.get_other_id(intersection),
+                to: to.0.get_other_id(intersection),
+            })
+        }
+        None => {
+            let intersection = to.entry_id();
+            let boundary = map.boundary_for(intersection)?;
+            if boundary == intersection {
+                return None;
+            }
+            Some(Movement {
+                intersection,
+                from: boundary,
+                to: to.0.get_other_id(intersection),
+            })
+        }
+    }
+}
+
+///road lit for each destination by the route indicator on the approach of `movement`, `None` if
+///it has no indicator
+pub fn indicated_roads(
+    movement: &Movement,
+    indicators: &[RouteIndicator],
+    map: &RoadMap,
+) -> Option<HashMap<IntersectionId, RoadId>> {
+    let approach = movement.approach(map)?;
+    let indicator = indicators
+        .iter()
+        .find(|i| i.road_id == approach && i.int_id == movement.intersection)?;
+    Some(
+        indicator
+            .routes
+            .keys()
+            .filter_map(|destination| {
+                map.best_direction(indicator.int_id, *destination, Some(&indicator.road_id))
+                    .1
+                    .map(|road| (*destination, road))
+            })
+            .collect(),
+    )
+}
+
+///a movement made past a route indicator, waiting for the vehicle's trip to end
+#[derive(Debug, Clone)]
+struct PendingMovement {
+    movement: Movement,
+    ///road lit for each destination when the movement was made
+    lit: HashMap<IntersectionId, RoadId>,
+}
+
+/// movement counts over one time bin
+#[derive(Debug, Clone)]
+pub struct MovementBin {
+    pub start: Instant,
+    pub end: Instant,
+    pub counts: HashMap<Movement, usize>,
+    ///movements made from an approach with a route indicator by vehicles heading to one of its
+    ///destinations, counted when their trip ends
+    pub indicated: HashMap<Movement, usize>,
+    ///indicated movements that left by the road lit for the vehicle's destination at the time
+    pub followed: HashMap<Movement, usize>,
+    ///road changes between roads that do not meet, ie a road was missed in between
+    pub unresolved: usize,
+}
+
+impl MovementBin {
+    fn new(start: Instant) -> Self {
+        Self {
+            start,
+            end: start,
+            counts: HashMap::new(),
+            indicated: HashMap::new(),
+            followed: HashMap::new(),
+            unresolved: 0,
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.counts.values().sum()
+    }
+
+    ///share of the movements made past a route indicator that followed it
+    pub fn compliance(&self) -> Option<f64> {
+        let indicated = self.indicated.values().sum::<usize>();
+        if indicated == 0 {
+            return None;
+        }
+        Some(self.followed.values().sum::<usize>() as f64 / indicated as f64)
+    }
+
+    ///one line per movement, grouped by intersection
+    pub fn format_table(&self) -> String {
+        let mut movements = self.counts.iter().collect::<Vec<_>>();
+        movements.sort_by_key(|(m, _)| (m.intersection.0, m.from.0, m.to.0));
+
+        let mut s = String::new();
+        for (movement, count) in movements {
+            s += &format!(
+                "at {}: {} -> {} {:>5}",
+                movement.intersection, movement.from, movement.to, count
+            );
+            if let Some(indicated) = self.indicated.get(movement) {
+                s += &format!(
+                    "  followed indicator {}/{}",
+                    self.followed.get(movement).unwrap_or(&0),
+                    indicated
+                );
+            }
+            s += "\n";
+        }
+        s += &format!("{} movements, {} unresolved", self.total(), self.unresolved);
+        if let Some(compliance) = self.compliance() {
+            s += &format!(", indicator compliance {:.0}%", compliance * 100.0);
+        }
+        s
+    }
+}
+
+/// turning movement counts at every intersection over fixed time bins
+pub struct TurningMovements {
+    bin: Duration,
+    pub current: MovementBin,
+    ///closed bins, oldest first
+    pub history: VecDeque<MovementBin>,
+    pending: HashMap<VehicleId, Vec<PendingMovement>>,
+}
+
+impl TurningMovements {
+    pub fn new(bin: Duration) -> Self {
+        Self {
+            bin,
+            current: MovementBin::new(Instant::now()),
+            history: VecDeque::new(),
+            pending: HashMap::new(),
+        }
+    }
+
+    ///counts the movement of a transition, keeping the route indicators as they are lit now to
+    ///check it against once the vehicle's destination is known
+    pub fn record(
+        &mut self,
+        transition: &LaneTransition,
+        indicators: &[RouteIndicator],
+        map: &RoadMap,
+    ) {
+        let movement = match transition_movement(transition, map) {
+            Some(movement) => movement,
+            None => {
+                if transition.from.is_some() {
+                    self.current.unresolved += 1;
+                }
+                return;
+            }
+        };
+        *self.current.counts.entry(movement).or_insert(0) += 1;
+
+        if let Some(lit) = indicated_roads(&movement, indicators, map) {
+            self.pending
+                .entry(transition.vehicle)
+                .or_default()
+                .push(PendingMovement { movement, lit });
+        }
+    }
+
+    ///checks the indicated movements of a finished trip against the road lit for its destination,
+    ///movements of trips with an unknown destination or one the indicator doesn't show are left out
+    pub fn record_trip(&mut self, trip: &Trip, map: &RoadMap) {
+        let pending = std::iter::once(&trip.id)
+            .chain(&trip.merged_ids)
+            .filter_map(|id| self.pending.remove(id))
+            .flatten()
+            .collect::<Vec<PendingMovement>>();
+        let destination = match trip_endpoints(trip, map) {
+            Some((_, destination)) => destination,
+            None => return,
+        };
+        for PendingMovement { movement, lit } in pending {
+            let Some(road) = lit.get(&destination) else {
+                continue;
+            };
+            *self.current.indicated.entry(movement).or_insert(0) += 1;
+            if movement.exit(map) == Some(*road) {
+                *self.current.followed.entry(movement).or_insert(0) += 1;
+            }
+        }
+    }
+
+    ///closes the current bin once it has run for the configured duration and returns it
+    pub fn update(&mut self, now: Instant) -> Option<&MovementBin> {
+        if now.saturating_duration_since(self.current.start) < self.bin {
+            return None;
+        }
+        let mut closed = std::mem::replace(&mut self.current, MovementBin::new(now));
+        closed.end = now;
+        self.history.push_back(closed);
+        if self.history.len() > HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.back()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::map::LaneId;
+    use crate::presets;
+
+    fn drive(
+        movements: &mut TurningMovements,
+        id: u64,
+        lanes: &[LaneId],
+        indicators: &[RouteIndicator],
+        map: &RoadMap,
+    ) {
+        for pair in lanes.windows(2) {
+            let transition = LaneTransition {
+                vehicle: VehicleId(id),
+                from: Some(pair[0]),
+                to: pair[1],
+            };
+            movements.record(&transition, indicators, map);
+        }
+        let trip = Trip {
+            id: VehicleId(id),
+            merged_ids: vec![],
+            lanes: lanes.to_vec(),
+            start: Instant::now(),
+            end: Instant::now(),
+        };
+        movements.record_trip(&trip, map);
+    }
+
+    #[test]
+    fn compliance_is_judged_by_the_trip_destination() {
+        let map = presets::create_map();
+        let indicators = presets::create_route_indicators();
+        let a = 'a' as u32;
+        let from_a = LaneId(RoadId(a, 1), 0);
+        let to_2 = LaneId(RoadId(1, 2), 0);
+        let mut movements = TurningMovements::new(Duration::from_secs(60));
+
+        // 1-2 is lit for b, 1-4 for d
+        let to_b = LaneId(RoadId('b' as u32, 2), 1);
+        drive(&mut movements, 1, &[from_a, to_2, to_b], &indicators, &map);
+        let to_d = [LaneId(RoadId(2, 4), 0), LaneId(RoadId('d' as u32, 4), 1)];
+        drive(
+            &mut movements,
+            2,
+            &[from_a, to_2, to_d[0], to_d[1]],
+            &indicators,
+            &map,
+        );
+
+        let past_indicator = Movement {
+            intersection: IntersectionId(1),
+            from: IntersectionId(a),
+            to: IntersectionId(2),
+        };
+        assert_eq!(movements.current.indicated[&past_indicator], 2);
+        assert_eq!(movements.current.followed[&past_indicator], 1);
+        // vehicle 2 then follows the indicator on 1-2 to 4
+        assert_eq!(movements.current.compliance(), Some(2.0 / 3.0));
+        assert!(movements.pending.is_empty());
+    }
+}
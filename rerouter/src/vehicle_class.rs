@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::{Serialize, Serializer};
+
+///vehicles up to this long (m) are taken for motorbikes when the detector gives no class
+const MAX_MOTORBIKE_LENGTH: f64 = 2.5;
+const MAX_CAR_LENGTH: f64 = 5.5;
+const MAX_VAN_LENGTH: f64 = 7.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VehicleClass {
+    Motorbike,
+    Car,
+    Van,
+    Bus,
+    Truck,
+}
+
+impl VehicleClass {
+    ///the most likely class of a vehicle `length` meters long
+    pub fn from_length(length: f64) -> Self {
+        if length <= MAX_MOTORBIKE_LENGTH {
+            VehicleClass::Motorbike
+        } else if length <= MAX_CAR_LENGTH {
+            VehicleClass::Car
+        } else if length <= MAX_VAN_LENGTH {
+            VehicleClass::Van
+        } else {
+            VehicleClass::Truck
+        }
+    }
+}
+
+impl FromStr for VehicleClass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "motorbike" | "motorcycle" | "bike" => Ok(VehicleClass::Motorbike),
+            "car" => Ok(VehicleClass::Car),
+            "van" => Ok(VehicleClass::Van),
+            "bus" => Ok(VehicleClass::Bus),
+            "truck" | "lorry" => Ok(VehicleClass::Truck),
+            _ => Err(format!("unknown vehicle class '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for VehicleClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            VehicleClass::Motorbike => "motorbike",
+            VehicleClass::Car => "car",
+            VehicleClass::Van => "van",
+            VehicleClass::Bus => "bus",
+            VehicleClass::Truck => "truck",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Serialize for VehicleClass {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// passenger car equivalent of each vehicle class, stored as a plain text file of
+/// `<class> <weight>` lines, ie `bus 3.0`
+///
+/// classes missing from the file keep their default weight
+#[derive(Debug, Clone, PartialEq)]
+pub struct PceWeights(pub HashMap<VehicleClass, f64>);
+
+impl Default for PceWeights {
+    fn default() -> Self {
+        Self(HashMap::from([
+            (VehicleClass::Motorbike, 0.5),
+            (VehicleClass::Car, 1.0),
+            (VehicleClass::Van, 1.5),
+            (VehicleClass::Bus, 3.0),
+            (VehicleClass::Truck, 3.0),
+        ]))
+    }
+}
+
+impl PceWeights {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, io::Error> {
+        let contents = fs::read_to_string(path)?;
+        let invalid = |msg: String| io::Error::new(ErrorKind::InvalidData, msg);
+
+        let mut weights = Self::default();
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let words = line.split_whitespace().collect::<Vec<&str>>();
+            if words.len() != 2 {
+                return Err(invalid(format!(
+                    "line {}: expected '<class> <weight>'",
+                    n + 1
+                )));
+            }
+            let class = words[0]
+                .parse::<VehicleClass>()
+                .map_err(|e| invalid(format!("line {}: {}", n + 1, e)))?;
+            let weight = words[1]
+                .parse::<f64>()
+                .map_err(|e| invalid(format!("line {}: {}", n + 1, e)))?;
+            weights.0.insert(class, weight);
+        }
+        Ok(weights)
+    }
+
+    pub fn weight(&self, class: VehicleClass) -> f64 {
+        *self.0.get(&class).unwrap_or(&1.0)
+    }
+}
@@ -1,175 +1,764 @@
 use std::{
-    collections::{HashMap, HashSet, VecDeque},
+    collections::{HashMap, HashSet},
+    io::ErrorKind,
     time::{Duration, Instant},
 };
 
-use image::{GenericImageView, ImageBuffer, Luma, LumaA};
-
 use crate::{
-    map::{LaneId, RoadId},
-    mask_loader::load_road_masks,
+    calibration::{Calibration, Homography},
+    config::Config,
+    kalman::MotionFilter,
+    lane_geometry::{LaneGeometry, LaneLocator},
+    map::{LaneId, RoadId, RoadMap},
+    mask_loader::{load_road_masks, RoadMasks},
+    queue::{estimate_queue, QueueEstimate, STOPPED_SPEED},
+    travel_time::TravelTimes,
+    vehicle_class::{PceWeights, VehicleClass},
     RxData,
 };
+use log::{debug, error, info};
+use serde::Serialize;
+use traffic_cost::{CostModel, CostParams, LaneState};
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq, PartialOrd, Ord, Hash)]
-pub struct VehicleId(u64);
+#[derive(Debug, Clone, Copy, Eq, PartialEq, PartialOrd, Ord, Hash, Serialize)]
+pub struct VehicleId(pub u64);
+
+impl std::fmt::Display for VehicleId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
 
 //represents a vehicle being tracked
 #[derive(Debug)]
 pub struct Vehicle {
     pub id: VehicleId,
-    vel: f64,
+    ///position in camera pixels
     pub pos: (f64, f64),
+    ///filtered position in map coordinates (meters)
+    pub world_pos: (f64, f64),
+    ///filtered speed in m/s
     pub avg_vel: f64,
-    vel_history: VecDeque<f64>,
+    ///filtered direction of travel in radians
+    pub heading: f64,
+    pub filter: MotionFilter,
     last_detect: Instant,
-    first_detect: Instant,
-    update_count: usize,
+    ///last time the detector reported a different position for this vehicle
+    last_moved: Instant,
+    last_raw_pos: (f64, f64),
     pub lane_id: LaneId,
+    ///when the vehicle came onto its current road from another one, `None` if it was first seen on it
+    lane_entered: Option<Instant>,
+    ///every lane the vehicle was seen on in order, a switch to the other lane of the same road replaces the last entry
+    pub lane_history: Vec<LaneId>,
+    first_detect: Instant,
+    stopped_since: Option<Instant>,
+    ///distance travelled along the lane in meters, when the lane geometry is known
+    pub lane_pos: Option<f64>,
+    ///scores of the competing lanes when the last lane assignment was ambiguous, empty otherwise
+    pub lane_candidates: Vec<(LaneId, f64)>,
+    ///detector ids that were re-identified as this vehicle, oldest first
+    pub merged_ids: Vec<VehicleId>,
+    ///class reported by the detector, if any
+    pub class: Option<VehicleClass>,
+    ///smoothed length and width in meters, if the detector reports sizes
+    pub size: Option<(f64, f64)>,
 }
 
 impl Vehicle {
-    pub fn new(id: VehicleId, pos: (f64, f64), vel: f64, lane_id: LaneId) -> Self {
+    pub fn new(id: VehicleId, pos: (f64, f64), world_pos: (f64, f64), lane_id: LaneId) -> Self {
         Self {
             id,
             pos,
-            vel,
+            world_pos,
             lane_id,
             last_detect: Instant::now(),
+            last_moved: Instant::now(),
+            last_raw_pos: pos,
+            avg_vel: 0.0,
+            heading: 0.0,
+            filter: MotionFilter::new(world_pos),
+            lane_entered: None,
+            lane_history: vec![lane_id],
             first_detect: Instant::now(),
-            avg_vel: vel,
-            vel_history: VecDeque::with_capacity(52),
-            update_count: 0,
+            stopped_since: None,
+            lane_pos: None,
+            lane_candidates: vec![],
+            merged_ids: vec![],
+            class: None,
+            size: None,
         }
     }
 
-    pub fn update(&mut self, pos: (f64, f64), vel: f64, lane_id: LaneId) {
-        self.pos = pos;
-        self.vel = vel;
-        self.vel_history.push_back(vel);
-        if self.vel_history.len() > 50 {
-            self.vel_history.pop_front();
+    ///takes in the class and size (in meters, either way round) of a detection
+    pub fn observe_shape(&mut self, class: Option<VehicleClass>, size: Option<(f64, f64)>) {
+        if class.is_some() {
+            self.class = class;
+        }
+        if let Some((a, b)) = size {
+            let (length, width) = (a.max(b), a.min(b));
+            self.size = Some(match self.size {
+                Some((l, w)) => (
+                    l + SIZE_LEARN_RATE * (length - l),
+                    w + SIZE_LEARN_RATE * (width - w),
+                ),
+                None => (length, width),
+            });
+        }
+    }
+
+    ///class reported by the detector, otherwise guessed from the size (only known with a camera
+    ///calibration), otherwise a car
+    pub fn vehicle_class(&self) -> VehicleClass {
+        self.class
+            .or_else(|| {
+                self.size
+                    .map(|(length, _)| VehicleClass::from_length(length))
+            })
+            .unwrap_or(VehicleClass::Car)
+    }
+
+    ///corrects the motion filter with a new detection
+    ///
+    ///the detector keeps reporting the last position of a blob it lost, so a repeated position is
+    ///only trusted once it has been repeated for longer than `stopped_after` (ie, the vehicle stopped)
+    pub fn update(
+        &mut self,
+        pos: (f64, f64),
+        world_pos: (f64, f64),
+        vel: f64,
+        lane_id: LaneId,
+        stopped_after: Duration,
+    ) {
+        let now = Instant::now();
+        self.filter.predict_to(now);
+        let moved = pos != self.last_raw_pos;
+        if moved {
+            self.last_raw_pos = pos;
+            self.last_moved = now;
+        }
+        if moved || self.time_since_moved() > stopped_after {
+            self.filter.correct_position(world_pos);
+            self.filter.correct_speed(vel);
+        }
+        self.sync_filter();
+        self.last_detect = now;
+        if lane_id != self.lane_id {
+            match self.lane_history.last_mut() {
+                Some(last) if last.0 == lane_id.0 => *last = lane_id,
+                _ => self.lane_history.push(lane_id),
+            }
         }
-        self.avg_vel = self.vel_history.iter().sum::<f64>() / self.vel_history.len() as f64;
-        self.last_detect = Instant::now();
         self.lane_id = lane_id;
-        self.update_count += 1;
+    }
+
+    ///extrapolates the motion filter to `now` while the vehicle is not detected
+    pub fn predict(&mut self, now: Instant) {
+        self.filter.predict_to(now);
+        self.sync_filter();
+    }
+
+    fn sync_filter(&mut self) {
+        self.world_pos = self.filter.pos();
+        self.avg_vel = self.filter.speed();
+        self.heading = self.filter.heading();
+        if self.avg_vel < STOPPED_SPEED {
+            self.stopped_since.get_or_insert_with(Instant::now);
+        } else {
+            self.stopped_since = None;
+        }
+    }
+
+    ///how long the vehicle has been stopped, zero while it is moving
+    pub fn stopped_for(&self) -> Duration {
+        self.stopped_since
+            .map(|t| t.elapsed())
+            .unwrap_or(Duration::ZERO)
     }
 
     pub fn time_till_last_detect(&self) -> Duration {
         Instant::now().duration_since(self.last_detect)
     }
+
+    pub fn time_since_moved(&self) -> Duration {
+        Instant::now().duration_since(self.last_moved)
+    }
+
+    ///the motion filter is sure enough of the direction of travel to compare it with lanes
+    pub fn has_heading(&self) -> bool {
+        self.avg_vel > MIN_HEADING_SPEED && self.filter.vel_std() < MAX_VEL_STD
+    }
+
+    ///the detector stopped reporting this vehicle
+    ///
+    ///a repeated position is not enough, stopped and queued vehicles are reported that way too
+    pub fn is_lost(&self) -> bool {
+        self.time_till_last_detect().as_millis() > LOST_TIME
+    }
+}
+
+///the lanes a vehicle was seen on, from its first detection until its tracking timed out
+#[derive(Debug, Clone)]
+pub struct Trip {
+    pub id: VehicleId,
+    ///ids that were re-identified as the vehicle, oldest first
+    pub merged_ids: Vec<VehicleId>,
+    pub lanes: Vec<LaneId>,
+    pub start: Instant,
+    pub end: Instant,
+}
+
+///a vehicle moving onto another road, `from` is `None` when the vehicle was first detected
+#[derive(Debug, Clone, Copy)]
+pub struct LaneTransition {
+    pub vehicle: VehicleId,
+    pub from: Option<LaneId>,
+    pub to: LaneId,
+}
+
+///time without a fresh detection after which a vehicle becomes a re-identification candidate
+const LOST_TIME: u128 = 200;
+///distance (m) around the predicted position in which a new id can be matched to a lost vehicle
+const REID_GATE: f64 = 2.0;
+///time (ms) after a new id first appears during which it can still be matched to a lost vehicle,
+///the detector keeps reporting a blob it lost under the old id for a second after giving it a new one
+const REID_WINDOW: u128 = 1500;
+
+///velocity estimates less certain than this (m/s) are left out of the average lane speed
+const MAX_VEL_STD: f64 = 1.5;
+///vehicles slower than this (m/s) have no usable heading
+const MIN_HEADING_SPEED: f64 = 0.3;
+
+///weight of each new detection in the smoothed vehicle size
+const SIZE_LEARN_RATE: f64 = 0.2;
+
+///weights of the lane assignment score
+const MEMBERSHIP_WEIGHT: f64 = 1.0;
+const HEADING_WEIGHT: f64 = 0.6;
+const CONTINUITY_WEIGHT: f64 = 0.3;
+///lane assignments won by less than this score are reported as ambiguous
+const AMBIGUITY_MARGIN: f64 = 0.15;
+///weight of each new vehicle heading in the learnt lane direction
+const HEADING_LEARN_RATE: f64 = 0.02;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LaneAssignment {
+    Unique(LaneId),
+    ///the best lane did not win clearly, `candidates` holds every matching lane and its score, best first
+    Ambiguous {
+        lane: LaneId,
+        candidates: Vec<(LaneId, f64)>,
+    },
 }
 
-const THRESH_TIME: u128 = 1300;
+impl LaneAssignment {
+    pub fn lane(&self) -> LaneId {
+        match self {
+            LaneAssignment::Unique(lane) => *lane,
+            LaneAssignment::Ambiguous { lane, .. } => *lane,
+        }
+    }
+}
 
 pub struct Tracker {
     pub lanes: HashMap<LaneId, HashSet<VehicleId>>,
     pub vehicles: HashMap<VehicleId, Vehicle>,
-    road_masks: HashMap<LaneId, ImageBuffer<LumaA<u8>, Vec<u8>>>,
+    lane_locator: Box<dyn LaneLocator>,
+    calibration: Homography,
+    ///whether `calibration` maps pixels to meters, detection sizes are left in pixels otherwise
+    calibrated: bool,
+    to_pixels: Homography,
+    ///ids replaced through re-identification, ignored while the detector keeps reporting them
+    retired_ids: HashMap<VehicleId, Instant>,
+    ///average direction of travel (as a unit vector) of the vehicles on each lane
+    lane_headings: HashMap<LaneId, (f64, f64)>,
+    ///number of detections whose lane could not be told apart from another
+    pub ambiguous_assignments: usize,
+    ///number of detections ignored for coming from a retired id or from outside every lane
+    pub dropped_detections: usize,
+    ///observed times of complete lane traversals
+    pub travel_times: HashMap<LaneId, TravelTimes>,
+    ///trips of vehicles whose tracking timed out, until taken by `take_finished_trips`
+    finished_trips: Vec<Trip>,
+    ///road changes of tracked vehicles, until taken by `take_transitions`
+    transitions: Vec<LaneTransition>,
+    ///passenger car equivalents the lane densities are counted in
+    pub pce_weights: PceWeights,
+    ///time without a detection after which a vehicle is dropped
+    pub track_timeout: Duration,
 }
 
 impl Tracker {
-    pub fn new() -> Self {
-        let road_masks = load_road_masks();
-        let lanes = road_masks.keys().map(|i| (*i, HashSet::new())).collect();
-        println!("created the following lanes from mask files: {:?}", lanes);
+    ///lanes are only taken from the lane geometry if all of its roads are on `map`
+    pub fn new(config: &Config, map: &RoadMap) -> Self {
+        let lane_geometry_path = config.lane_geometry.display();
+        let lane_geometry = match LaneGeometry::load(&config.lane_geometry, map) {
+            Ok(geometry) => {
+                info!("Loaded lane geometry from {}", lane_geometry_path);
+                Some(geometry)
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                info!(
+                    "No lane geometry ({}: {}), using road masks",
+                    lane_geometry_path, e
+                );
+                None
+            }
+            Err(e) => {
+                error!(
+                    "Invalid lane geometry {}: {}, using road masks",
+                    lane_geometry_path, e
+                );
+                None
+            }
+        };
+        let lane_locator: Box<dyn LaneLocator> = match lane_geometry {
+            Some(geometry) => Box::new(geometry),
+            None => Box::new(RoadMasks(load_road_masks(&config.road_masks))),
+        };
+        let calibration_path = config.calibration.display();
+        let calibration = match Calibration::load(&config.calibration) {
+            Ok(c) => {
+                info!(
+                    "Loaded calibration for camera {} from {}",
+                    c.camera, calibration_path
+                );
+                Some(c.homography)
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                info!(
+                    "No camera calibration ({}: {}), using pixel coordinates",
+                    calibration_path, e
+                );
+                None
+            }
+            Err(e) => {
+                error!(
+                    "Invalid camera calibration {}: {}, using pixel coordinates",
+                    calibration_path, e
+                );
+                None
+            }
+        };
+        let pce_weights_path = config.pce_weights.display();
+        let pce_weights = match PceWeights::load(&config.pce_weights) {
+            Ok(weights) => {
+                info!("Loaded vehicle class weights from {}", pce_weights_path);
+                weights
+            }
+            Err(e) => {
+                info!(
+                    "No vehicle class weights ({}: {}), using defaults",
+                    pce_weights_path, e
+                );
+                PceWeights::default()
+            }
+        };
+        Self::with_locator(lane_locator, calibration, pce_weights, config.track_timeout)
+    }
+
+    ///positions are taken as map coordinates without a `calibration`, which is expected to be
+    ///invertible
+    fn with_locator(
+        lane_locator: Box<dyn LaneLocator>,
+        calibration: Option<Homography>,
+        pce_weights: PceWeights,
+        track_timeout: Duration,
+    ) -> Self {
+        let lanes = lane_locator
+            .lane_ids()
+            .iter()
+            .map(|i| (*i, HashSet::new()))
+            .collect();
+        debug!("created the following lanes: {:?}", lanes);
+        let calibrated = calibration.is_some();
+        let calibration = calibration.unwrap_or_else(Homography::identity);
+        let to_pixels = calibration
+            .inverse()
+            .expect("camera calibration is not invertible");
         Self {
             lanes,
             vehicles: HashMap::new(),
-            road_masks,
+            lane_locator,
+            calibration,
+            calibrated,
+            to_pixels,
+            retired_ids: HashMap::new(),
+            lane_headings: HashMap::new(),
+            ambiguous_assignments: 0,
+            dropped_detections: 0,
+            travel_times: HashMap::new(),
+            finished_trips: vec![],
+            transitions: vec![],
+            pce_weights,
+            track_timeout,
         }
     }
 
-    ///compares vehicle position with road masks to find out on which road the vehicle is
-    fn lane_from_pos(&self, pos: (f64, f64)) -> Option<&LaneId> {
-        for (lane_id, mask) in &self.road_masks {
-            if mask.get_pixel(pos.0 as u32, pos.1 as u32).0[0] >= 200u8 {
-                return Some(lane_id);
-            }
-        }
-        None
+    ///traffic state of the lane as seen by the cost models, `lane_length` is in meters
+    pub fn lane_state(&self, lane_id: &LaneId, lane_length: f64) -> Option<LaneState> {
+        let lane = self.lanes.get(lane_id)?;
+        let lane_pos = lane
+            .iter()
+            .filter_map(|id| self.vehicles.get(id))
+            .map(|v| v.lane_pos)
+            .collect::<Option<Vec<f64>>>();
+        Some(LaneState {
+            length: lane_length,
+            vehicles: lane.len(),
+            pce: self.lane_pce(lane_id)?,
+            avg_speed: self.lane_avg_speed(lane_id),
+            clearance: lane_pos
+                .filter(|pos| !pos.is_empty())
+                .map(|pos| pos.into_iter().fold(lane_length, f64::min).max(0.0)),
+            queue_length: self.lane_queue(lane_id, lane_length)?.length,
+            travel_time: self.lane_travel_time(lane_id),
+        })
     }
 
-    ///calculates the dynamic part of the cost value(ie, traffic density and average speed)
+    ///calculates the dynamic part of the cost value with the given cost model
     pub fn lane_dynamic_cost(
         &self,
         lane_id: &LaneId,
         lane_length: f64,
-        density_coeff: f64,
-        vel_coeff: f64,
-        clearance_coeff: f64,
+        model: &dyn CostModel,
+        params: &CostParams,
     ) -> Option<f64> {
-        let lane = self.lanes.get(lane_id);
-        if let None = lane {
-            return None;
-        }
-        let lane = lane.unwrap();
-        let lane_len = lane.len() as f64;
+        Some(model.lane_cost(&self.lane_state(lane_id, lane_length)?, params))
+    }
 
-        let density_term = density_coeff * lane_len / lane_length;
+    ///load of the lane in passenger car equivalents
+    pub fn lane_pce(&self, lane_id: &LaneId) -> Option<f64> {
+        Some(
+            self.lanes
+                .get(lane_id)?
+                .iter()
+                .filter_map(|id| self.vehicles.get(id))
+                .map(|v| self.pce_weights.weight(v.vehicle_class()))
+                .sum(),
+        )
+    }
+
+    ///average speed (m/s) of the vehicles on the lane whose speed estimate has settled
+    pub fn lane_avg_speed(&self, lane_id: &LaneId) -> Option<f64> {
         let mut vel_n = 0;
         let mut avg_vel = 0.0;
-        for id in lane {
+        for id in self.lanes.get(lane_id)? {
             if let Some(v) = self.vehicles.get(id) {
-                if v.update_count > 50 && v.first_detect.elapsed().as_millis() > THRESH_TIME * 2 {
+                if v.filter.vel_std() < MAX_VEL_STD {
                     avg_vel += v.avg_vel;
                     vel_n += 1;
                 }
             }
         }
 
-        let avg_vel = if vel_n > 0 {
-            avg_vel / vel_n as f64
+        if vel_n > 0 {
+            Some(avg_vel / vel_n as f64)
         } else {
-            0.0
-        };
+            None
+        }
+    }
 
-        let inv_velocity_term = density_term * vel_coeff * 1.0 / (10e-2 + avg_vel);
-        let inv_velocity_term = if avg_vel > 0.0 {
-            inv_velocity_term
-        } else {
-            0.0
-        };
-        let clearance_term = density_term * clearance_coeff * 0.0;
+    ///length (m) of the line positions along the lane are measured on, `road_length` when the
+    ///lane geometry does not give it
+    pub fn lane_length(&self, lane_id: &LaneId, road_length: f64) -> f64 {
+        self.lane_locator
+            .lane_length(lane_id)
+            .unwrap_or(road_length)
+    }
 
-        let cost = density_term + inv_velocity_term + clearance_term;
-        Some(cost)
+    ///stopped vehicles and queue length at the downstream end of the lane,
+    ///`road_length` is used when the lane geometry does not give the lane length
+    ///
+    ///vehicles whose speed estimate has not settled are left out, new tracks start at rest
+    pub fn lane_queue(&self, lane_id: &LaneId, road_length: f64) -> Option<QueueEstimate> {
+        let lane_length = self.lane_length(lane_id, road_length);
+        let vehicles = self
+            .lanes
+            .get(lane_id)?
+            .iter()
+            .filter_map(|id| self.vehicles.get(id))
+            .filter(|v| v.filter.vel_std() < MAX_VEL_STD)
+            .map(|v| (v.lane_pos, v.avg_vel))
+            .collect::<Vec<(Option<f64>, f64)>>();
+        Some(estimate_queue(&vehicles, lane_length))
     }
 
     ///called when new data is received from vehicel tracker
     pub fn on_recv(&mut self, data: RxData) {
-        let lane = self.lane_from_pos((data.x, data.y));
-        if let None = lane {
+        let pos = (data.x, data.y);
+        let world_pos = self.calibration.apply(pos);
+        let v_id = VehicleId(data.id);
+        if let Some(last_seen) = self.retired_ids.get_mut(&v_id) {
+            *last_seen = Instant::now();
+            self.dropped_detections += 1;
             return;
         }
-        let lane = lane.unwrap().clone();
-        let v_id = VehicleId(data.id);
+        let assignment = match self.assign_lane(pos, world_pos, self.vehicles.get(&v_id)) {
+            Some(assignment) => assignment,
+            None => {
+                self.dropped_detections += 1;
+                return;
+            }
+        };
+        let lane = assignment.lane();
+        let lane_candidates = match assignment {
+            LaneAssignment::Unique(_) => vec![],
+            LaneAssignment::Ambiguous { candidates, .. } => {
+                self.ambiguous_assignments += 1;
+                candidates
+            }
+        };
+        let scale = self.calibration.scale_at(pos);
+        let vel = data.vel * scale;
+        // pixel sizes would be classed as trucks and weigh on the lane density
+        let size = data
+            .size
+            .filter(|_| self.calibrated)
+            .map(|(w, h)| (w * scale, h * scale));
+        let appeared = match self.vehicles.get(&v_id) {
+            None => Some(Instant::now()),
+            Some(vehicle) if vehicle.first_detect.elapsed().as_millis() < REID_WINDOW => {
+                Some(vehicle.first_detect)
+            }
+            Some(_) => None,
+        };
+        if let Some(old_id) = appeared.and_then(|t| self.reidentify(world_pos, vel, &lane, t)) {
+            self.merge_track(old_id, v_id);
+        }
+
         if self.vehicles.contains_key(&v_id) {
             let vehicle = self.vehicles.get_mut(&v_id).unwrap();            
             if vehicle.lane_id != lane {
                 self.lanes.get_mut(&vehicle.lane_id).unwrap().remove(&vehicle.id);
                 self.lanes.get_mut(&lane).unwrap().insert(v_id);
             }
-			vehicle.update((data.x, data.y), data.vel, lane);
+            // switching between the two lanes of a road is a misassignment, not a traversal
+            if vehicle.lane_id.0 != lane.0 {
+                let now = Instant::now();
+                if let Some(entered) = vehicle.lane_entered {
+                    self.travel_times
+                        .entry(vehicle.lane_id)
+                        .or_default()
+                        .record(now, now.duration_since(entered).as_secs_f64());
+                }
+                self.transitions.push(LaneTransition {
+                    vehicle: v_id,
+                    from: Some(vehicle.lane_id),
+                    to: lane,
+                });
+                vehicle.lane_entered = Some(now);
+            }
+			vehicle.update(pos, world_pos, vel, lane, self.track_timeout);
+            vehicle.pos = self.to_pixels.apply(vehicle.world_pos);
+            vehicle.lane_pos = self.lane_locator.distance_along(&lane, vehicle.world_pos);
+            vehicle.lane_candidates = lane_candidates;
         } else {
-            self.vehicles
-                .insert(v_id, Vehicle::new(v_id, (data.x, data.y), data.vel, lane));
+            let mut vehicle = Vehicle::new(v_id, pos, world_pos, lane);
+            vehicle.lane_pos = self.lane_locator.distance_along(&lane, world_pos);
+            vehicle.lane_candidates = lane_candidates;
+            self.vehicles.insert(v_id, vehicle);
             self.lanes.get_mut(&lane).unwrap().insert(v_id);
+            self.transitions.push(LaneTransition {
+                vehicle: v_id,
+                from: None,
+                to: lane,
+            });
         }
+
+        let vehicle = self.vehicles.get_mut(&v_id).unwrap();
+        vehicle.observe_shape(data.class, size);
+        if vehicle.lane_candidates.is_empty() && vehicle.has_heading() {
+            let heading = vehicle.heading;
+            self.learn_lane_direction(&lane, heading);
+        }
+    }
+
+    ///scores every lane the position lies on by how strongly it belongs to the lane, how well the
+    ///vehicle's heading matches the lane direction and whether the vehicle was already on it
+    fn assign_lane(
+        &self,
+        pos: (f64, f64),
+        world_pos: (f64, f64),
+        prev: Option<&Vehicle>,
+    ) -> Option<LaneAssignment> {
+        let mut candidates = self
+            .lanes
+            .keys()
+            .filter_map(|lane| {
+                let membership = self.lane_locator.membership(lane, pos, world_pos);
+                if membership <= 0.0 {
+                    return None;
+                }
+                let mut score = MEMBERSHIP_WEIGHT * membership;
+                if let Some(vehicle) = prev {
+                    if vehicle.lane_id == *lane {
+                        score += CONTINUITY_WEIGHT;
+                    }
+                    if let (true, Some(dir)) =
+                        (vehicle.has_heading(), self.lane_direction(lane, world_pos))
+                    {
+                        score += HEADING_WEIGHT * (vehicle.heading - dir).cos();
+                    }
+                }
+                Some((*lane, score))
+            })
+            .collect::<Vec<(LaneId, f64)>>();
+        // ties are broken by lane id so the result never depends on hash map order
+        candidates.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        match candidates.len() {
+            0 => None,
+            1 => Some(LaneAssignment::Unique(candidates[0].0)),
+            _ if candidates[0].1 - candidates[1].1 >= AMBIGUITY_MARGIN => {
+                Some(LaneAssignment::Unique(candidates[0].0))
+            }
+            _ => Some(LaneAssignment::Ambiguous {
+                lane: candidates[0].0,
+                candidates,
+            }),
+        }
+    }
+
+    ///direction of travel on the lane, from the lane geometry or else learnt from past vehicles
+    pub fn lane_direction(&self, lane: &LaneId, world_pos: (f64, f64)) -> Option<f64> {
+        self.lane_locator
+            .direction(lane, world_pos)
+            .or_else(|| self.lane_headings.get(lane).map(|(x, y)| y.atan2(*x)))
     }
 
-    ///removes vehicles whose tracking has timed out
+    fn learn_lane_direction(&mut self, lane: &LaneId, heading: f64) {
+        let (x, y) = self.lane_headings.entry(*lane).or_insert((0.0, 0.0));
+        *x = *x * (1.0 - HEADING_LEARN_RATE) + heading.cos() * HEADING_LEARN_RATE;
+        *y = *y * (1.0 - HEADING_LEARN_RATE) + heading.sin() * HEADING_LEARN_RATE;
+    }
+
+    pub fn take_finished_trips(&mut self) -> Vec<Trip> {
+        std::mem::take(&mut self.finished_trips)
+    }
+
+    pub fn take_transitions(&mut self) -> Vec<LaneTransition> {
+        std::mem::take(&mut self.transitions)
+    }
+
+    ///median of the observed traversal times of the lane in seconds
+    pub fn lane_travel_time(&self, lane_id: &LaneId) -> Option<f64> {
+        self.travel_times.get(lane_id).and_then(|t| t.median())
+    }
+
+    ///finds the lost vehicle that an id first reported at `appeared` most likely belongs to,
+    ///scored by distance to its predicted position, lane and speed
+    ///
+    ///only vehicles seen before the id appeared and no longer moving by then are candidates
+    fn reidentify(
+        &self,
+        world_pos: (f64, f64),
+        vel: f64,
+        lane: &LaneId,
+        appeared: Instant,
+    ) -> Option<VehicleId> {
+        let mut best = None;
+        let mut best_score = f64::INFINITY;
+        for vehicle in self.vehicles.values() {
+            if !vehicle.is_lost()
+                || vehicle.first_detect >= appeared
+                || vehicle.last_moved > appeared
+            {
+                continue;
+            }
+
+            let gate = REID_GATE + 3.0 * vehicle.filter.pos_std();
+            let dist = (vehicle.world_pos.0 - world_pos.0).hypot(vehicle.world_pos.1 - world_pos.1);
+            if dist > gate {
+                continue;
+            }
+
+            let lane_term = if vehicle.lane_id == *lane {
+                0.0
+            } else if vehicle.lane_id.0 == lane.0 {
+                0.5
+            } else {
+                1.0
+            };
+            let speed_term = (vehicle.avg_vel - vel).abs() / vehicle.avg_vel.max(1.0);
+
+            let score = dist / gate + lane_term + speed_term;
+            if score < best_score {
+                best_score = score;
+                best = Some(vehicle.id);
+            }
+        }
+        best
+    }
+
+    ///moves the track of `old_id` over to `new_id`, keeping its motion state and history
+    ///
+    ///if `new_id` is already tracked it keeps its own motion state and takes over the history
+    fn merge_track(&mut self, old_id: VehicleId, new_id: VehicleId) {
+        let mut vehicle = self.vehicles.remove(&old_id).unwrap();
+        self.lanes
+            .get_mut(&vehicle.lane_id)
+            .unwrap()
+            .remove(&old_id);
+        vehicle.merged_ids.push(old_id);
+        match self.vehicles.get_mut(&new_id) {
+            Some(new) => {
+                new.first_detect = vehicle.first_detect;
+                if new.lane_entered.is_none() && vehicle.lane_id.0 == new.lane_id.0 {
+                    new.lane_entered = vehicle.lane_entered;
+                }
+                let mut lane_history = vehicle.lane_history;
+                for lane in new.lane_history.drain(..) {
+                    match lane_history.last_mut() {
+                        Some(last) if last.0 == lane.0 => *last = lane,
+                        _ => lane_history.push(lane),
+                    }
+                }
+                new.lane_history = lane_history;
+                vehicle.merged_ids.append(&mut new.merged_ids);
+                new.merged_ids = vehicle.merged_ids;
+                new.class = new.class.or(vehicle.class);
+                new.size = new.size.or(vehicle.size);
+            }
+            None => {
+                self.lanes.get_mut(&vehicle.lane_id).unwrap().insert(new_id);
+                vehicle.id = new_id;
+                self.vehicles.insert(new_id, vehicle);
+            }
+        }
+        self.retired_ids.insert(old_id, Instant::now());
+    }
+
+    ///removes vehicles whose tracking has timed out and predicts the rest through missed detections
     pub fn update(&mut self) {
+        let now = Instant::now();
         let mut remove_list = vec![];
-        for (_, vehicle) in &self.vehicles {
-            if vehicle.time_till_last_detect().as_millis() > THRESH_TIME {
+        for vehicle in self.vehicles.values_mut() {
+            if vehicle.time_till_last_detect() > self.track_timeout {
                 remove_list.push(vehicle.id);
                 continue;
             }
+            vehicle.predict(now);
+            vehicle.pos = self.to_pixels.apply(vehicle.world_pos);
+            vehicle.lane_pos = self
+                .lane_locator
+                .distance_along(&vehicle.lane_id, vehicle.world_pos);
         }
         for id in remove_list {
-            self.vehicles.remove(&id);
+            if let Some(vehicle) = self.vehicles.remove(&id) {
+                self.finished_trips.push(Trip {
+                    id,
+                    merged_ids: vehicle.merged_ids,
+                    lanes: vehicle.lane_history,
+                    start: vehicle.first_detect,
+                    end: vehicle.last_detect,
+                });
+            }
+        }
+        self.retired_ids
+            .retain(|_, last_seen| last_seen.elapsed() <= self.track_timeout);
+        for travel_times in self.travel_times.values_mut() {
+            travel_times.prune(now);
         }
 
         let mut remove_list = vec![];
@@ -191,3 +780,118 @@ impl Tracker {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::thread::sleep;
+
+    use super::*;
+    use crate::lane_geometry::LaneShape;
+
+    const LANE: LaneId = LaneId(RoadId(1, 2), 0);
+
+    ///a straight 50 m lane along the x axis, with map coordinates as pixels
+    fn tracker() -> Tracker {
+        let shape = LaneShape::Polyline {
+            points: vec![(0.0, 0.0), (50.0, 0.0)],
+            width: 4.0,
+        };
+        let geometry = LaneGeometry {
+            lanes: HashMap::from([(LANE, shape)]),
+        };
+        Tracker::with_locator(
+            Box::new(geometry),
+            None,
+            PceWeights::default(),
+            Duration::from_millis(1300),
+        )
+    }
+
+    fn detect(tracker: &mut Tracker, id: u64, x: f64) {
+        tracker.on_recv(RxData {
+            id,
+            x,
+            y: 0.0,
+            vel: 0.0,
+            class: None,
+            size: None,
+        });
+    }
+
+    #[test]
+    fn stopped_vehicle_keeps_its_id() {
+        let mut tracker = tracker();
+        for _ in 0..6 {
+            detect(&mut tracker, 1, 10.0);
+            sleep(Duration::from_millis(50));
+        }
+        let stopped = &tracker.vehicles[&VehicleId(1)];
+        assert!(stopped.time_since_moved().as_millis() > LOST_TIME);
+
+        detect(&mut tracker, 2, 11.0);
+        detect(&mut tracker, 1, 10.0);
+        assert!(tracker.vehicles.contains_key(&VehicleId(1)));
+        assert!(tracker.vehicles.contains_key(&VehicleId(2)));
+        assert_eq!(tracker.lanes[&LANE].len(), 2);
+        assert_eq!(tracker.dropped_detections, 0);
+    }
+
+    #[test]
+    fn lost_vehicle_is_reidentified() {
+        let mut tracker = tracker();
+        detect(&mut tracker, 1, 10.0);
+        sleep(Duration::from_millis(LOST_TIME as u64 + 50));
+
+        detect(&mut tracker, 2, 10.5);
+        assert!(!tracker.vehicles.contains_key(&VehicleId(1)));
+        let merged = &tracker.vehicles[&VehicleId(2)].merged_ids;
+        assert_eq!(merged, &vec![VehicleId(1)]);
+
+        detect(&mut tracker, 1, 10.0);
+        assert_eq!(tracker.vehicles.len(), 1);
+        assert_eq!(tracker.dropped_detections, 1);
+    }
+
+    #[test]
+    fn switched_id_is_reidentified_while_the_old_one_is_still_reported() {
+        let mut tracker = tracker();
+        detect(&mut tracker, 1, 10.0);
+        // the detector keeps reporting the lost blob where it was, next to its new id
+        for _ in 0..4 {
+            sleep(Duration::from_millis(50));
+            detect(&mut tracker, 1, 10.0);
+            detect(&mut tracker, 2, 10.5);
+        }
+        assert_eq!(tracker.lanes[&LANE].len(), 2);
+
+        sleep(Duration::from_millis(LOST_TIME as u64 + 50));
+        detect(&mut tracker, 2, 10.5);
+        assert_eq!(tracker.vehicles.len(), 1);
+        assert_eq!(tracker.lanes[&LANE].len(), 1);
+        let vehicle = &tracker.vehicles[&VehicleId(2)];
+        assert_eq!(vehicle.merged_ids, vec![VehicleId(1)]);
+        assert_eq!(vehicle.lane_history, vec![LANE]);
+    }
+    #[test]
+    fn pixel_sizes_leave_vehicles_as_cars() {
+        let mut tracker = tracker();
+        tracker.on_recv(RxData {
+            id: 1,
+            x: 10.0,
+            y: 0.0,
+            vel: 0.0,
+            class: None,
+            size: Some((20.0, 10.0)),
+        });
+        let vehicle = &tracker.vehicles[&VehicleId(1)];
+        assert_eq!(vehicle.vehicle_class(), VehicleClass::Car);
+        assert_eq!(tracker.lane_pce(&LANE), Some(1.0));
+    }
+    #[test]
+    fn new_tracks_are_not_queued() {
+        let mut tracker = tracker();
+        detect(&mut tracker, 1, 48.0);
+        let queue = tracker.lane_queue(&LANE, 50.0).unwrap();
+        assert_eq!(queue, QueueEstimate::default());
+    }
+}
@@ -0,0 +1,313 @@
+//! lane cost models shared by the rerouter and the simulator
+//!
+//! every model turns the traffic state of a lane into the dynamic part of its routing cost, which
+//! is added to the static cost (the road length), so costs are in meters of extra distance
+
+///traffic state of one lane, in whatever length and time units the caller uses (meters and
+///seconds for the rerouter, pixels and simulation seconds for the simulator)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LaneState {
+    pub length: f64,
+    pub vehicles: usize,
+    ///load of the lane in passenger car equivalents, equal to `vehicles` when sizes are unknown
+    pub pce: f64,
+    ///average speed of the vehicles on the lane, `None` without a usable estimate
+    pub avg_speed: Option<f64>,
+    ///free distance from the start of the lane to the nearest vehicle, `None` if unknown
+    pub clearance: Option<f64>,
+    ///length of the queue at the downstream end of the lane
+    pub queue_length: f64,
+    ///measured time to traverse the lane, `None` without recent measurements
+    pub travel_time: Option<f64>,
+}
+
+impl LaneState {
+    ///passenger car equivalents per unit of lane length
+    pub fn density(&self) -> f64 {
+        if self.length > 0.0 {
+            self.pce / self.length
+        } else {
+            0.0
+        }
+    }
+}
+
+///coefficients tuned at runtime (ie, from the dashboard), shared by every model
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CostParams {
+    pub density_coeff: f64,
+    pub vel_coeff: f64,
+    pub clearance_coeff: f64,
+    pub queue_coeff: f64,
+}
+
+impl Default for CostParams {
+    fn default() -> Self {
+        Self {
+            density_coeff: 500.0,
+            vel_coeff: 0.0,
+            clearance_coeff: 0.0,
+            queue_coeff: 0.0,
+        }
+    }
+}
+
+impl CostParams {
+    ///share of the lane taken up by the queue, 1 means it spills back into the upstream junction
+    fn queue_term(&self, lane: &LaneState) -> f64 {
+        if lane.length > 0.0 {
+            self.queue_coeff * lane.queue_length / lane.length
+        } else {
+            0.0
+        }
+    }
+}
+
+pub trait CostModel: Send {
+    ///name the model is selected by
+    fn name(&self) -> &'static str;
+    ///dynamic cost of the lane
+    fn lane_cost(&self, lane: &LaneState, params: &CostParams) -> f64;
+}
+
+///speed (m/s) of free flowing traffic, 30km/h
+pub const FREE_FLOW_SPEED: f64 = 8.3;
+
+///names of the models `from_name` can build
+pub const MODEL_NAMES: [&str; 3] = [DensitySpeed::NAME, Bpr::NAME, TravelTime::NAME];
+
+///builds a model with its default settings from its name
+pub fn from_name(name: &str) -> Option<Box<dyn CostModel>> {
+    match name {
+        DensitySpeed::NAME => Some(Box::new(DensitySpeed::default())),
+        Bpr::NAME => Some(Box::new(Bpr::default())),
+        TravelTime::NAME => Some(Box::new(TravelTime::default())),
+        _ => None,
+    }
+}
+
+///the model after `name` in `MODEL_NAMES`, wrapping around
+pub fn next_model_name(name: &str) -> &'static str {
+    let idx = MODEL_NAMES.iter().position(|n| *n == name).unwrap_or(0);
+    MODEL_NAMES[(idx + 1) % MODEL_NAMES.len()]
+}
+
+/// density weighted by the inverse of the average speed:
+/// `density + density * vel_coeff / (min_speed + avg_speed)`
+/// plus the clearance and queue terms
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DensitySpeed {
+    ///keeps the inverse speed term finite for nearly stopped lanes
+    pub min_speed: f64,
+}
+
+impl DensitySpeed {
+    pub const NAME: &'static str = "density";
+}
+
+impl Default for DensitySpeed {
+    fn default() -> Self {
+        Self { min_speed: 0.1 }
+    }
+}
+
+impl CostModel for DensitySpeed {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn lane_cost(&self, lane: &LaneState, params: &CostParams) -> f64 {
+        let density_term = params.density_coeff * lane.density();
+        let inv_velocity_term = match lane.avg_speed {
+            Some(speed) if speed > 0.0 => {
+                density_term * params.vel_coeff / (self.min_speed + speed)
+            }
+            _ => 0.0,
+        };
+        // grows as the free space at the start of the lane shrinks
+        let clearance_term = match lane.clearance {
+            Some(clearance) if lane.length > 0.0 => {
+                density_term * params.clearance_coeff * (1.0 - clearance / lane.length).max(0.0)
+            }
+            _ => 0.0,
+        };
+        density_term + inv_velocity_term + clearance_term + params.queue_term(lane)
+    }
+}
+
+/// Bureau of Public Roads volume-delay function, `t = t0 * (1 + alpha * (v/c)^beta)`
+///
+/// the delay over the free flow time is converted back to a distance at free flow speed, which
+/// makes the cost `length * alpha * (v/c)^beta`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bpr {
+    pub alpha: f64,
+    pub beta: f64,
+    ///vehicles per unit of lane length the lane can hold before it is saturated
+    pub capacity: f64,
+}
+
+impl Bpr {
+    pub const NAME: &'static str = "bpr";
+}
+
+impl Default for Bpr {
+    fn default() -> Self {
+        Self {
+            alpha: 0.15,
+            beta: 4.0,
+            // one vehicle every 7m
+            capacity: 1.0 / 7.0,
+        }
+    }
+}
+
+impl CostModel for Bpr {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn lane_cost(&self, lane: &LaneState, params: &CostParams) -> f64 {
+        let saturation = lane.density() / self.capacity;
+        lane.length * self.alpha * saturation.powf(self.beta) + params.queue_term(lane)
+    }
+}
+
+/// delay measured from lane traversal times, as the extra distance that could have been driven at
+/// free flow speed in that time
+///
+/// without measured traversals the time is estimated from the average speed on the lane
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TravelTime {
+    pub free_flow_speed: f64,
+}
+
+impl TravelTime {
+    pub const NAME: &'static str = "travel-time";
+}
+
+impl Default for TravelTime {
+    fn default() -> Self {
+        Self {
+            free_flow_speed: FREE_FLOW_SPEED,
+        }
+    }
+}
+
+impl CostModel for TravelTime {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn lane_cost(&self, lane: &LaneState, params: &CostParams) -> f64 {
+        let travel_time = lane.travel_time.or(match lane.avg_speed {
+            Some(speed) if speed > 0.0 && lane.vehicles > 0 => Some(lane.length / speed),
+            _ => None,
+        });
+        let delay = match travel_time {
+            Some(t) => (t - lane.length / self.free_flow_speed).max(0.0),
+            None => 0.0,
+        };
+        delay * self.free_flow_speed + params.queue_term(lane)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///4 cars on a 100m lane
+    fn lane() -> LaneState {
+        LaneState {
+            length: 100.0,
+            vehicles: 4,
+            pce: 4.0,
+            ..LaneState::default()
+        }
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn density_speed() {
+        let model = DensitySpeed::default();
+        let mut params = CostParams::default();
+        assert!(close(model.lane_cost(&lane(), &params), 20.0));
+
+        params.vel_coeff = 2.0;
+        let moving = LaneState {
+            avg_speed: Some(1.9),
+            ..lane()
+        };
+        assert!(close(model.lane_cost(&moving, &params), 40.0));
+
+        params.clearance_coeff = 1.0;
+        params.queue_coeff = 50.0;
+        let queued = LaneState {
+            clearance: Some(25.0),
+            queue_length: 40.0,
+            ..lane()
+        };
+        assert!(close(model.lane_cost(&queued, &params), 20.0 + 15.0 + 20.0));
+        let empty = LaneState {
+            length: 0.0,
+            ..lane()
+        };
+        assert_eq!(model.lane_cost(&empty, &params), 0.0);
+    }
+
+    #[test]
+    fn bpr() {
+        let model = Bpr::default();
+        let params = CostParams::default();
+        let saturated = LaneState {
+            pce: 100.0 / 7.0,
+            ..lane()
+        };
+        assert!(close(model.lane_cost(&saturated, &params), 15.0));
+        let half = LaneState {
+            pce: 50.0 / 7.0,
+            ..lane()
+        };
+        assert!(close(model.lane_cost(&half, &params), 15.0 / 16.0));
+        assert_eq!(model.lane_cost(&LaneState::default(), &params), 0.0);
+    }
+
+    #[test]
+    fn travel_time() {
+        let model = TravelTime::default();
+        let params = CostParams::default();
+        let free_flow = 100.0 / FREE_FLOW_SPEED;
+        let measured = LaneState {
+            travel_time: Some(free_flow + 10.0),
+            avg_speed: Some(1.0),
+            ..lane()
+        };
+        assert!(close(
+            model.lane_cost(&measured, &params),
+            10.0 * FREE_FLOW_SPEED
+        ));
+        let estimated = LaneState {
+            avg_speed: Some(FREE_FLOW_SPEED / 2.0),
+            ..lane()
+        };
+        assert!(close(model.lane_cost(&estimated, &params), 100.0));
+        let fast = LaneState {
+            travel_time: Some(free_flow / 2.0),
+            ..lane()
+        };
+        assert_eq!(model.lane_cost(&fast, &params), 0.0);
+        assert_eq!(model.lane_cost(&lane(), &params), 0.0);
+    }
+
+    #[test]
+    fn models_by_name() {
+        for name in MODEL_NAMES {
+            assert_eq!(from_name(name).unwrap().name(), name);
+        }
+        assert!(from_name("fastest").is_none());
+        assert_eq!(next_model_name(TravelTime::NAME), DensitySpeed::NAME);
+    }
+}
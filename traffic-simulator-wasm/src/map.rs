@@ -1,5 +1,7 @@
 use rustc_hash::{FxHashMap, FxHashSet};
 
+use traffic_cost::{CostModel, CostParams, LaneState};
+
 use crate::simulator::{Vehicle, VehicleId};
 
 #[derive(Hash, Eq, PartialEq, Clone, Copy, Debug)]
@@ -149,8 +151,8 @@ impl Lane {
     pub fn update(
         &mut self,
         vehicles: &FxHashMap<VehicleId, Vehicle>,
-        density_coeff: f64,
-        vel_coeff: f64,
+        model: &dyn CostModel,
+        params: &CostParams,
     ) {
         let dir = self.dir;
         self.lane_buff
@@ -165,17 +167,15 @@ impl Lane {
             .filter(|(_, infront)| infront.is_some())
             .map(|(id, infront)| (*id, *infront.unwrap()))
             .collect();
-        self.update_dynamic_cost(vehicles, density_coeff, vel_coeff);
+        self.update_dynamic_cost(vehicles, model, params);
     }
 
     fn update_dynamic_cost(
         &mut self,
         vehicles: &FxHashMap<VehicleId, Vehicle>,
-        density_coeff: f64,
-        vel_coeff: f64,
+        model: &dyn CostModel,
+        params: &CostParams,
     ) {
-        let density_term = density_coeff * self.lane.len() as f64 / self.length;
-
         let mut vel_n = 0;
         let mut avg_vel = 0.0;
         for (id, _) in &self.lane {
@@ -185,20 +185,24 @@ impl Lane {
             }
         }
 
-        let avg_vel = if vel_n > 0 {
-            avg_vel / vel_n as f64
-        } else {
-            0.0
-        };
-
-        let inv_velocity_term = density_term * vel_coeff / (10e-6 + avg_vel);
-        let inv_velocity_term = if avg_vel > 0.0 {
-            inv_velocity_term
-        } else {
-            0.0
+        let state = LaneState {
+            length: self.length,
+            vehicles: self.lane.len(),
+            pce: self.lane.len() as f64,
+            avg_speed: if vel_n > 0 {
+                Some(avg_vel / vel_n as f64)
+            } else {
+                None
+            },
+            // the lane is sorted from its start to its end
+            clearance: self
+                .lane
+                .first()
+                .map(|(_, pos)| (pos - self.start_pos).abs()),
+            queue_length: 0.0,
+            travel_time: None,
         };
-
-        self.dynamic_cost = density_term + inv_velocity_term;
+        self.dynamic_cost = model.lane_cost(&state, params);
     }
 }
 
@@ -309,12 +313,12 @@ impl Road {
     pub fn update(
         &mut self,
         vehicles: &FxHashMap<VehicleId, Vehicle>,
-        density_coeff: f64,
-        vel_coeff: f64,
+        model: &dyn CostModel,
+        params: &CostParams,
     ) {
-        self.fwd_lane.update(vehicles, density_coeff, vel_coeff);
+        self.fwd_lane.update(vehicles, model, params);
         self.cost_dynamic.0 = self.fwd_lane.dynamic_cost;
-        self.bck_lane.update(vehicles, density_coeff, vel_coeff);
+        self.bck_lane.update(vehicles, model, params);
         self.cost_dynamic.1 = self.bck_lane.dynamic_cost;
     }
 }
@@ -346,13 +350,13 @@ impl RoadMap {
     pub fn update(
         &mut self,
         vehicles: &FxHashMap<VehicleId, Vehicle>,
-        density_coeff: f64,
-        vel_coeff: f64,
+        model: &dyn CostModel,
+        params: &CostParams,
         cache_expiry_delta_frame: u32,
     ) {
         self.delta_cache_frame += 1;
         for road in self.roads.values_mut() {
-            road.update(vehicles, density_coeff, vel_coeff)
+            road.update(vehicles, model, params)
         }
         if self.delta_cache_frame >= cache_expiry_delta_frame {
             self.best_route_cache.clear();
@@ -1,5 +1,6 @@
 use rand::seq::SliceRandom;
 use rustc_hash::FxHashMap;
+use traffic_cost::{Bpr, CostModel, CostParams, DensitySpeed, TravelTime};
 use wasm_bindgen::prelude::*;
 
 use crate::map::*;
@@ -266,6 +267,23 @@ impl StatsManager {
     }
 }
 
+/// cost models scaled to simulator units (pixels and simulation seconds)
+fn cost_model(name: &str) -> Option<Box<dyn CostModel>> {
+    match name {
+        DensitySpeed::NAME => Some(Box::new(DensitySpeed { min_speed: 10e-6 })),
+        Bpr::NAME => Some(Box::new(Bpr {
+            // vehicles keep about 12px apart
+            capacity: 1.0 / 12.0,
+            ..Bpr::default()
+        })),
+        // average of the vehicles' top speeds
+        TravelTime::NAME => Some(Box::new(TravelTime {
+            free_flow_speed: 85.0,
+        })),
+        _ => None,
+    }
+}
+
 #[wasm_bindgen]
 pub struct Simulator {
     vehicles: FxHashMap<VehicleId, Vehicle>,
@@ -274,6 +292,7 @@ pub struct Simulator {
     vehicle_render_buff: Vec<f32>,
     pub stats: StatsManager,
     node_weight_map: FxHashMap<IntersectionId, f64>,
+    cost_model: Box<dyn CostModel>,
 
     vehicle_remove_list: Vec<VehicleId>,
 }
@@ -294,6 +313,7 @@ impl Simulator {
             vehicle_render_buff: vec![],
             stats: StatsManager::new(),
             node_weight_map: FxHashMap::default(),
+            cost_model: cost_model(DensitySpeed::NAME).unwrap(),
             vehicle_remove_list: Vec::with_capacity(300),
         }
     }
@@ -384,14 +404,34 @@ impl Simulator {
             self.stats.update_frame(&self.vehicles);
         }
         self.vehicle_remove_list.clear();
-        self.map.update(
-            &self.vehicles,
+        let params = CostParams {
             density_coeff,
             vel_coeff,
+            ..CostParams::default()
+        };
+        self.map.update(
+            &self.vehicles,
+            self.cost_model.as_ref(),
+            &params,
             (1500.0 / scale.clamp(0.01, 100.0)) as u32,
         );
     }
 
+    ///switches the lane cost model, returns false for an unknown model name
+    pub fn set_cost_model(&mut self, name: &str) -> bool {
+        match cost_model(name) {
+            Some(model) => {
+                self.cost_model = model;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn cost_model_name(&self) -> String {
+        self.cost_model.name().to_string()
+    }
+
     pub fn create_intersection(&mut self, id: u32, x: u32, y: u32, weight: Option<f64>) {
         self.map.create_intersection(IntersectionId(id), (x, y));
         if let Some(weight) = weight {
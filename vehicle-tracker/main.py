@@ -75,6 +75,8 @@ cv2.createTrackbar('erode', 'threshold settings', 1, 20, nothing)
 
 # tracker = object_tracker.ObjectTracker(10, 0.05)
 data = {}
+# width and height of each tracked blob in pixels
+sizes = {}
 buffer = {}
 thresh_dist = 20
 thresh_time = 1.0
@@ -170,10 +172,12 @@ while True:
                             nearest_vel = vel
                 if not found:
                     data[counter] = ((cx, cy), time.monotonic(), 0, 0)
+                    sizes[counter] = rect[1]
                     counter += 1
                 else:
                     data[nearest_key] = (
                         (cx, cy), time.monotonic(), nearest_vel, nearest_angle)
+                    sizes[nearest_key] = rect[1]
 
                 # cv2.drawContours(img, [box], 0, color, 3)
             cv2.drawContours(img, [approx], 0, (255,0,255), 1)
@@ -183,7 +187,12 @@ while True:
     to_remove = []
     s = ''
     for (key, ((x, y), t, vel, angle)) in data.items():
-        s += str(key) + ',' + str(x) + ',' + str(y) + ',' + str(vel) + ' '
+        s += str(key) + ',' + str(x) + ',' + str(y) + ',' + str(vel)
+        if key in sizes:
+            # no classifier yet, the rerouter guesses the class from the size
+            (w, h) = sizes[key]
+            s += ',-,' + str(w) + ',' + str(h)
+        s += ' '
         if time.monotonic() - t >= thresh_time:
             to_remove.append(key)
         else:
@@ -199,6 +208,7 @@ while True:
 
     for e in to_remove:
         data.pop(e)
+        sizes.pop(e, None)
 
     print(s)
     sys.stdout.flush()